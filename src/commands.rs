@@ -0,0 +1,309 @@
+// Subcommands, which do something else than running the checks.
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, Utc};
+
+use crate::config::file as conf;
+use crate::config::toml;
+use crate::output;
+use crate::scheduler::timeline::{self, DueTimes};
+use crate::types::{Command, Config, ConfigCommand, Metric};
+use crate::worker;
+
+/// How far ahead `jr list` looks for the next run of a check. Any weekly active
+/// window opens within it.
+const NEXT_DUE_HORIZON: Duration = Duration::from_secs(8 * 24 * 3600);
+
+/// Runs `command`, with the checks and settings of the command line. Returns the
+/// exit status of jr.
+pub fn run(command: Command, cmdline_config: &Config) -> i32 {
+    let config_file = cmdline_config.settings.config_file.as_deref();
+    match command {
+        Command::CheckConfig => check_config(config_file),
+        Command::List => list(&conf::load_config(cmdline_config)),
+        Command::Run { name } => run_check(&conf::load_config(cmdline_config), &name),
+        Command::Schedule { span } => schedule(&conf::load_config(cmdline_config), span),
+        Command::Config {
+            action: ConfigCommand::Convert { input, output },
+        } => convert(input.as_deref().or(config_file), output.as_deref()),
+    }
+}
+
+/// Prints the checks with their group, worker, interval and next due time.
+fn list(config: &Config) -> i32 {
+    let now = Utc::now();
+    let until = now + chrono::Duration::from_std(NEXT_DUE_HORIZON).unwrap_or_default();
+    let mut rows = vec![[
+        "NAME".to_string(),
+        "GROUP".to_string(),
+        "WORKER".to_string(),
+        "EVERY".to_string(),
+        "NEXT DUE".to_string(),
+    ]];
+    for metric in &config.checks {
+        let next_due = DueTimes::new(metric, &config.settings, now, until).next();
+        rows.push([
+            metric.short_name.clone(),
+            metric.group.clone(),
+            metric.function.clone(),
+            interval(metric),
+            next_due.map(local_time).unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+    let widths: Vec<usize> = (0..5)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    0
+}
+
+/// Runs the check called `name` once, and prints the resulting metric and what
+/// each of its outputs would send. Nothing is sent.
+fn run_check(config: &Config, name: &str) -> i32 {
+    let Some(metric) = config.checks.iter().find(|m| m.short_name == name) else {
+        eprintln!("No check called '{}'", name);
+        return 1;
+    };
+    let Some(mut worker) = worker::Registry::with_builtins().create(&metric.function) else {
+        eprintln!(
+            "Unknown worker '{}' for check '{}'",
+            metric.function, metric.short_name
+        );
+        return 1;
+    };
+    if let Err(e) = worker.validate_args(&metric.args) {
+        eprintln!("Invalid arguments for check '{}': {}", metric.short_name, e);
+        return 1;
+    }
+    if let Err(e) = worker.setup(metric) {
+        eprintln!("Failed to set up check '{}': {}", metric.short_name, e);
+        return 1;
+    }
+    println!(
+        "Running {} {}",
+        metric.function,
+        metric.redact(&metric.args)
+    );
+    let mut result = worker.run(metric.without_result());
+    result.apply_thresholds();
+    worker.teardown();
+
+    println!("{}", result.redact(&format!("{:#?}", result)));
+    let outputs = output::Registry::with_builtins();
+    for name in &result.outputs {
+        match outputs.create(name).map(|output| output.render(&result)) {
+            Some(Some(rendered)) => {
+                println!("{} would send:\n{}", name, result.redact(&rendered))
+            }
+            Some(None) => println!("{} doesn't tell what it would send", name),
+            None => println!("Unknown output '{}'", name),
+        }
+    }
+    0
+}
+
+/// Prints when each check would run in the next `span`.
+fn schedule(config: &Config, span: Duration) -> i32 {
+    let now = Utc::now();
+    let until = now + chrono::Duration::from_std(span).unwrap_or_default();
+    let runs = timeline::timeline(&config.checks, &config.settings, now, until);
+    for (at, index) in &runs {
+        let metric = &config.checks[*index];
+        println!(
+            "{}  {} ({})",
+            local_time(*at),
+            metric.short_name,
+            metric.group
+        );
+    }
+    println!(
+        "{} run(s) of {} check(s) until {}",
+        runs.len(),
+        config.checks.len(),
+        local_time(until)
+    );
+    0
+}
+
+/// The interval of a check as configured: its cron expression or a number of seconds.
+fn interval(metric: &Metric) -> String {
+    match (&metric.cron, metric.once) {
+        (_, true) => "once".to_string(),
+        (Some(cron), false) => cron.to_string(),
+        (None, false) => format!("{}s", metric.n),
+    }
+}
+
+fn local_time(at: DateTime<Utc>) -> String {
+    at.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Reports every error in the configuration, and whatever would stop a check from
+/// running. Exits non-zero if there's any.
+fn check_config(path: Option<&Path>) -> i32 {
+    let Some(path) = conf::find_config(path) else {
+        eprintln!("No configuration found to check");
+        return 1;
+    };
+    let config = conf::read_config(Some(&path));
+    let mut errors = config.errors;
+    errors.extend(check_metrics(&config.checks));
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+    if errors.is_empty() {
+        println!(
+            "{}: {} check(s), no errors",
+            path.display(),
+            config.checks.len()
+        );
+        0
+    } else {
+        eprintln!("{}: {} error(s)", path.display(), errors.len());
+        1
+    }
+}
+
+/// What the parser can't tell: unknown workers and outputs, invalid worker
+/// arguments, duplicate names and thresholds whose minimum is above their maximum.
+fn check_metrics(metrics: &[Metric]) -> Vec<String> {
+    let workers = worker::Registry::with_builtins();
+    let outputs = output::Registry::with_builtins();
+    let mut errors = Vec::new();
+    let mut names: BTreeMap<&str, &str> = BTreeMap::new();
+    for metric in metrics {
+        let mut error = |message: String| errors.push(format!("{}: {}", metric.source, message));
+        match workers.create(&metric.function) {
+            Some(worker) => {
+                if let Err(e) = worker.validate_args(&metric.args) {
+                    error(format!(
+                        "Invalid arguments for check '{}': {}",
+                        metric.short_name, e
+                    ));
+                }
+            }
+            None => error(format!(
+                "Unknown worker '{}' for check '{}'",
+                metric.function, metric.short_name
+            )),
+        }
+        for name in &metric.outputs {
+            if outputs.create(name).is_none() {
+                error(format!(
+                    "Unknown output '{}' for check '{}'",
+                    name, metric.short_name
+                ));
+            }
+        }
+        let thresholds = [
+            ("min_value", metric.min_value, "max_value", metric.max_value),
+            (
+                "warn_min_value",
+                metric.warn_min_value,
+                "warn_max_value",
+                metric.warn_max_value,
+            ),
+        ];
+        for (min_key, min, max_key, max) in thresholds {
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    error(format!(
+                        "{} {} is above {} {} for check '{}'",
+                        min_key, min, max_key, max, metric.short_name
+                    ));
+                }
+            }
+        }
+        match names.entry(&metric.short_name) {
+            Entry::Occupied(first) => error(format!(
+                "Duplicate check name '{}', first defined at {}",
+                metric.short_name,
+                first.get()
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(&metric.source);
+            }
+        }
+    }
+    errors
+}
+
+/// Writes the configuration at `input` (or the one jr would read) in the TOML format.
+/// `${...}` references are kept as they are.
+fn convert(input: Option<&Path>, output: Option<&Path>) -> i32 {
+    let Some(path) = conf::find_config(input) else {
+        eprintln!("No configuration found to convert");
+        return 1;
+    };
+    let config = conf::read_config_raw(Some(&path));
+    for error in &config.errors {
+        eprintln!("{}", error);
+    }
+    if !config.errors.is_empty() {
+        eprintln!("Fix the errors in {} before converting it", path.display());
+        return 1;
+    }
+    let converted = toml::to_toml(&config);
+    match output {
+        Some(output) => {
+            if let Err(e) = fs::write(output, converted) {
+                eprintln!("Failed to write {}: {}", output.display(), e);
+                return 1;
+            }
+        }
+        None => print!("{}", converted),
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, worker: &str, source: &str) -> Metric {
+        Metric {
+            short_name: name.to_string(),
+            function: worker.to_string(),
+            args: "localhost".to_string(),
+            source: source.to_string(),
+            outputs: vec!["stdout".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_metrics() {
+        assert!(check_metrics(&[check("load", "load_avg", "jr.conf:1")]).is_empty());
+
+        let metrics = vec![
+            check("web", "check_ulr", "jr.conf:1"),
+            Metric {
+                min_value: Some(5.0),
+                max_value: Some(1.0),
+                outputs: vec!["carrier".to_string()],
+                ..check("web", "load_avg", "jr.conf:2")
+            },
+        ];
+        assert_eq!(
+            check_metrics(&metrics),
+            vec![
+                "jr.conf:1: Unknown worker 'check_ulr' for check 'web'",
+                "jr.conf:2: Unknown output 'carrier' for check 'web'",
+                "jr.conf:2: min_value 5 is above max_value 1 for check 'web'",
+                "jr.conf:2: Duplicate check name 'web', first defined at jr.conf:1",
+            ]
+        );
+    }
+}
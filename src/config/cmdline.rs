@@ -2,17 +2,29 @@ use clap::Parser;
 use std::env;
 use std::ffi::OsString;
 
-use crate::types::{Args, Metric};
+use crate::config::{file, options};
+use crate::types::{Args, Config, Metric, Settings};
 
-pub fn parse_config() -> Vec<Metric> {
-    let args = env::args_os().collect::<Vec<_>>();
-    parse_config_from_args(args)
+pub fn parse_args() -> Args {
+    Args::parse()
 }
 
-pub fn parse_config_from_args(args: Vec<OsString>) -> Vec<Metric> {
+#[cfg(test)]
+pub fn parse_config_from_args(args: Vec<OsString>) -> Config {
+    config_from_args(Args::parse_from(args))
+}
+
+/// The checks and settings given on the command line.
+pub fn config_from_args(args: Args) -> Config {
     // Initialize a vector to store Config structures
     let mut configs: Vec<Metric> = Vec::new();
-    let args = Args::parse_from(args);
+    let settings = Settings {
+        max_concurrency: args.max_concurrency,
+        config_file: args.config.clone(),
+        no_config: args.no_config,
+        nagios: args.nagios,
+        ..Default::default()
+    };
 
     if args.version {
         println!("jr {}", env!("CARGO_PKG_VERSION"));
@@ -26,6 +38,29 @@ pub fn parse_config_from_args(args: Vec<OsString>) -> Vec<Metric> {
 
     let every = args.every.unwrap_or(9999999);
 
+    let timeout = match args.timeout.map(|t| options::parse_timeout(&t.to_string())) {
+        Some(Ok(timeout)) => Some(timeout),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    for line in &args.checks {
+        match file::parse_check(line) {
+            Ok(metric) => configs.push(Metric {
+                once: args.once,
+                timeout: metric.timeout.or(timeout),
+                ..metric
+            }),
+            Err(e) => {
+                eprintln!("{} in --check '{}'", e, line);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Convert Vec<OsString> to String
     let remaining_args_str = args
         .remaining_args
@@ -36,7 +71,11 @@ pub fn parse_config_from_args(args: Vec<OsString>) -> Vec<Metric> {
         .join(" ");
 
     if remaining_args_str.is_empty() && (worker == "timethis" || worker == "runthis") {
-        return Vec::new();
+        return Config {
+            checks: configs,
+            settings,
+            ..Default::default()
+        };
     }
 
     println!("Arguments: {}", remaining_args_str);
@@ -55,9 +94,16 @@ pub fn parse_config_from_args(args: Vec<OsString>) -> Vec<Metric> {
         short_name: name.to_string(),
         min_value: args.min_value,
         max_value: args.max_value,
+        warn_min_value: args.warn_min_value,
+        warn_max_value: args.warn_max_value,
+        timeout,
         ..Default::default()
     });
-    configs
+    Config {
+        checks: configs,
+        settings,
+        ..Default::default()
+    }
 }
 
 fn placeholder_name(remaining_args_str: &str) -> String {
@@ -82,8 +128,67 @@ mod tests {
     #[test]
     fn test_worker_flag() {
         let args: Vec<OsString> = vec!["jr".into(), "--worker".into(), "test_worker".into()];
-        let config = parse_config_from_args(args);
+        let config = parse_config_from_args(args).checks;
         assert_eq!(config.len(), 1);
         assert_eq!(config[0].function, "test_worker");
     }
+
+    #[test]
+    fn test_max_concurrency_flag() {
+        let args: Vec<OsString> = vec!["jr".into(), "--max-concurrency".into(), "2".into()];
+        let config = parse_config_from_args(args);
+        assert!(config.checks.is_empty());
+        assert_eq!(config.settings.max_concurrency, Some(2));
+    }
+
+    #[test]
+    fn test_config_flag() {
+        let args: Vec<OsString> = vec!["jr".into(), "--config".into(), "/etc/jr/web.conf".into()];
+        let config = parse_config_from_args(args);
+        assert_eq!(
+            config.settings.config_file,
+            Some(std::path::PathBuf::from("/etc/jr/web.conf"))
+        );
+    }
+
+    #[test]
+    fn test_config_convert_command() {
+        use crate::types::{Args, Command, ConfigCommand};
+        use clap::Parser;
+        use std::path::PathBuf;
+
+        let args = Args::parse_from(["jr", "config", "convert", "jr.conf", "-o", "jr.toml"]);
+        match args.command {
+            Some(Command::Config {
+                action: ConfigCommand::Convert { input, output },
+            }) => {
+                assert_eq!(input, Some(PathBuf::from("jr.conf")));
+                assert_eq!(output, Some(PathBuf::from("jr.toml")));
+            }
+            _ => panic!("config convert not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_check_flags() {
+        let args: Vec<OsString> = vec![
+            "jr".into(),
+            "--no-config".into(),
+            "--once".into(),
+            "--check".into(),
+            "load;timeout=3::30::load_avg::mordor".into(),
+            "--check".into(),
+            "web::60::check_url::https://example.com".into(),
+        ];
+        let config = parse_config_from_args(args);
+        assert!(config.settings.no_config);
+        let checks = config.checks;
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].short_name, "load");
+        assert_eq!(checks[0].n, 30);
+        assert_eq!(checks[0].timeout, Some(std::time::Duration::from_secs(3)));
+        assert!(checks[0].once);
+        assert_eq!(checks[1].function, "check_url");
+        assert_eq!(checks[1].args, "https://example.com");
+    }
 }
@@ -1,104 +1,681 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use crate::types::Metric;
+use chrono_tz::Tz;
 
-fn parse_line(
-    line: &str,
-    curr_group: &str,
-    curr_min_value: Option<f64>,
-    curr_max_value: Option<f64>,
-) -> Option<Metric> {
-    // Trim any leading/trailing whitespace and split the line by "::" up to 3 parts
-    let mut parts = line.trim().splitn(4, "::");
+use crate::config::{interpolate, options, toml};
+use crate::output::{DEFAULT_OUTPUTS, DEFAULT_REPORTER};
+use crate::scheduler::calendar::{Cron, Window};
+use crate::types::{CatchUp, Config, Metric, Settings, Splay};
+
+const CONFIG_FILE: &str = "jr.conf";
+
+/// How deep `Include` directives can be nested, which stops include loops.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Settings inherited by the check lines that follow them. `Every`, `Outputs`,
+/// `Tags`, `Reporter`, `Catch-up`, `Jitter`, `Timezone`, `Active`, `Inactive`,
+/// `Timeout`, `Retries` and `Retry-interval` given before the first `Group` are
+/// global, the others only apply to the current group.
+#[derive(Clone)]
+struct Scope {
+    group: String,
+    /// The interval of checks that leave theirs empty, 0 if there's none.
+    every: u64,
+    cron: Option<Cron>,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    warn_min_value: Option<f64>,
+    warn_max_value: Option<f64>,
+    outputs: Vec<String>,
+    tags: Vec<String>,
+    reporter: String,
+    catch_up: CatchUp,
+    jitter: Duration,
+    timezone: Tz,
+    active: Option<Window>,
+    inactive_every: Option<u64>,
+    timeout: Option<Duration>,
+    retries: u32,
+    retry_interval: Option<Duration>,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope {
+            group: "Default".to_string(),
+            every: 0,
+            cron: None,
+            min_value: None,
+            max_value: None,
+            warn_min_value: None,
+            warn_max_value: None,
+            outputs: DEFAULT_OUTPUTS.iter().map(|o| o.to_string()).collect(),
+            tags: Vec::new(),
+            reporter: DEFAULT_REPORTER.to_string(),
+            catch_up: CatchUp::default(),
+            jitter: Duration::ZERO,
+            timezone: Tz::UTC,
+            active: None,
+            inactive_every: None,
+            timeout: None,
+            retries: 0,
+            retry_interval: None,
+        }
+    }
+}
+
+impl Scope {
+    /// A check with the settings of this scope.
+    fn template(&self) -> Metric {
+        Metric {
+            group: self.group.clone(),
+            n: self.every,
+            cron: self.cron.clone(),
+            min_value: self.min_value,
+            max_value: self.max_value,
+            warn_min_value: self.warn_min_value,
+            warn_max_value: self.warn_max_value,
+            outputs: self.outputs.clone(),
+            tags: self.tags.clone(),
+            reporter: self.reporter.clone(),
+            catch_up: self.catch_up,
+            jitter: self.jitter,
+            timezone: self.timezone,
+            active: self.active.clone(),
+            inactive_every: self.inactive_every,
+            timeout: self.timeout,
+            retries: self.retries,
+            retry_interval: self.retry_interval,
+            ..Default::default()
+        }
+    }
 
-    if line.trim().is_empty()
+    /// The scope whose template is `metric`.
+    fn from_template(metric: &Metric) -> Scope {
+        Scope {
+            group: metric.group.clone(),
+            every: metric.n,
+            cron: metric.cron.clone(),
+            min_value: metric.min_value,
+            max_value: metric.max_value,
+            warn_min_value: metric.warn_min_value,
+            warn_max_value: metric.warn_max_value,
+            outputs: metric.outputs.clone(),
+            tags: metric.tags.clone(),
+            reporter: metric.reporter.clone(),
+            catch_up: metric.catch_up,
+            jitter: metric.jitter,
+            timezone: metric.timezone,
+            active: metric.active.clone(),
+            inactive_every: metric.inactive_every,
+            timeout: metric.timeout,
+            retries: metric.retries,
+            retry_interval: metric.retry_interval,
+        }
+    }
+}
+
+/// Whether the line is empty or a comment starting with # or //.
+fn is_blank_or_comment(line: &str) -> bool {
+    line.trim().is_empty()
         || line.trim_start().starts_with('#')
         || line.trim_start().starts_with("//")
-    {
-        // Ignore empty lines and comment lines starting with # or //
+}
+
+/// Whether the line is the `directive` one. Its first word must be the keyword
+/// itself, so that checks named like `Active_users` aren't taken for directives.
+fn is_directive(line: &str, directive: &str) -> bool {
+    line.split_whitespace().next() == Some(directive)
+}
+
+/// The value of a threshold directive like `Min-value 5`. None resets it, when
+/// the value is missing or invalid.
+fn threshold(line: &str, directive: &str, errors: &mut Vec<String>) -> Option<f64> {
+    let value = line.split_whitespace().nth(1)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            errors.push(format!("Failed to parse {}", directive));
+            None
+        }
+    }
+}
+
+/// Parses a check line given on its own, like with `--check`, with the default
+/// settings.
+pub fn parse_check(line: &str) -> Result<Metric, String> {
+    let mut errors = Vec::new();
+    match parse_line(line, &Scope::default(), true, &mut errors) {
+        Some(metric) => Ok(metric),
+        None if errors.is_empty() => Err("Empty check".to_string()),
+        None => Err(errors.join(", ")),
+    }
+}
+
+/// Parses a check line. With `expand`, `${...}` references are expanded in the
+/// args and option values only, once the line is split, so that a value can't
+/// change the other fields.
+fn parse_line(line: &str, scope: &Scope, expand: bool, errors: &mut Vec<String>) -> Option<Metric> {
+    // Trim any leading/trailing whitespace and split the line by "::" up to 3 parts
+    let mut parts = line.trim().splitn(4, "::");
+
+    if is_blank_or_comment(line) {
+        // Ignore empty lines and comment lines
         None
-    } else if let (Some(short_name), Some(n_str), Some(function), Some(args)) =
+    } else if let (Some(name_field), Some(n_str), Some(function), Some(args)) =
         (parts.next(), parts.next(), parts.next(), parts.next())
     {
-        // Parse the first part as an unsigned 64-bit integer (`n`)
-        if let Ok(n) = n_str.parse::<u64>() {
-            if n == 0 {
-                eprintln!("Invalid N value in config file at line: {}", line);
+        // An empty interval is the one of the group, if it has one
+        let schedule = match n_str.trim().is_empty() && scope.every > 0 {
+            true => Ok((scope.every, scope.cron.clone())),
+            false => options::parse_schedule(n_str),
+        };
+        let (n, cron) = match schedule {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                errors.push(e.to_string());
+                return None;
+            }
+        };
+        let (short_name, check_options) = match options::split_name(name_field) {
+            Ok(split) => split,
+            Err(e) => {
+                errors.push(e.to_string());
+                return None;
+            }
+        };
+        let mut secrets = Vec::new();
+        let mut expanded = |text: &str| match expand {
+            true => interpolate::expand(text, &mut secrets),
+            false => Ok(text.to_string()),
+        };
+        let args = match expanded(args) {
+            Ok(args) => args,
+            Err(e) => {
+                errors.push(e);
+                return None;
+            }
+        };
+        let mut metric = Metric {
+            n,
+            cron,
+            function: function.to_string(),
+            args,
+            short_name: short_name.to_string(),
+            ..scope.template()
+        };
+        for (key, value) in check_options {
+            let applied =
+                expanded(value).and_then(|value| options::apply(&mut metric, key, &value));
+            if let Err(e) = applied {
+                errors.push(e);
                 return None;
             }
-            Some(Metric {
-                n,
-                function: function.to_string(),
-                args: args.to_string(),
-                short_name: short_name.to_string(),
-                group: curr_group.to_string(),
-                min_value: curr_min_value,
-                max_value: curr_max_value,
-                ..Default::default()
-            })
-        } else {
-            eprintln!("Failed to parse N value in config file at line: {}", line);
-            None
         }
+        Some(Metric { secrets, ..metric })
     } else {
-        eprintln!("Invalid format in config file at line: {}", line);
+        errors.push("Invalid format, expected name::seconds::worker::args".to_string());
         None
     }
 }
 
-pub fn parse_config() -> Vec<Metric> {
-    // Initialize a vector to store Config structures
-    let mut configs: Vec<Metric> = Vec::new();
-    let mut curr_group = "Default";
-    let mut curr_min_value: Option<f64> = None;
-    let mut curr_max_value: Option<f64> = None;
+/// Calls `on_change` from a background thread each time one of `files` is
+/// modified, looking at them `every` so often. `files` can be replaced while
+/// watching, e.g. after a reload: the new list is only compared with itself.
+pub fn watch(
+    files: Arc<Mutex<Vec<PathBuf>>>,
+    every: Duration,
+    on_change: impl Fn() + Send + 'static,
+) {
+    let modified = move || -> Vec<(PathBuf, Option<SystemTime>)> {
+        let files = files.lock().map(|files| files.clone()).unwrap_or_default();
+        files
+            .into_iter()
+            .map(|file| {
+                let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+                (file, modified)
+            })
+            .collect()
+    };
+    thread::spawn(move || {
+        let mut last = modified();
+        loop {
+            thread::sleep(every);
+            let current = modified();
+            // A new list comes from a reload, which read the files already
+            let same_files = current
+                .iter()
+                .map(|(file, _)| file)
+                .eq(last.iter().map(|(file, _)| file));
+            if current != last {
+                last = current;
+                if same_files {
+                    on_change();
+                }
+            }
+        }
+    });
+}
+
+/// Where jr looks for its configuration, in order, when `--config` isn't given.
+/// In each directory, jr.conf comes before jr.toml.
+fn search_path() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(".")];
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => dirs.push(Path::new(&dir).join("jr")),
+        _ => {
+            if let Some(home) = env::var_os("HOME") {
+                dirs.push(Path::new(&home).join(".config/jr"));
+            }
+        }
+    }
+    dirs.push(PathBuf::from("/etc/jr"));
+    dirs.iter()
+        .flat_map(|dir| [dir.join(CONFIG_FILE), dir.join("jr.toml")])
+        .collect()
+}
+
+/// The configuration file to read: `path` if given, else the first one found
+/// in the search path.
+pub fn find_config(path: Option<&Path>) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => search_path().into_iter().find(|path| path.is_file()),
+    }
+}
+
+/// Reads the configuration file at `path`, or the first one in the search path.
+/// The files of the `jr.conf.d` directory next to it are read after it. Without
+/// any configuration file, the configuration is empty.
+pub fn read_config(path: Option<&Path>) -> Config {
+    read(path, Parser::default())
+}
+
+/// Like `read_config`, leaving `${...}` references unexpanded, so that no secret
+/// ends up in the configuration.
+pub fn read_config_raw(path: Option<&Path>) -> Config {
+    read(
+        path,
+        Parser {
+            raw: true,
+            ..Default::default()
+        },
+    )
+}
+
+fn read(path: Option<&Path>, mut parser: Parser) -> Config {
+    if let Some(path) = find_config(path) {
+        let global = parser.parse_file(&path, Scope::default());
+        parser.parse_drop_ins(&path, global);
+    }
+    parser.into_config()
+}
 
-    // Read the configuration file. Return empty configs if no config file.
-    let config = match fs::read_to_string("jr.conf") {
-        Ok(content) => content,
-        Err(_) => return configs,
+/// Reads the configuration file, unless `--no-config` is given, and adds the
+/// checks and settings of the command line to it.
+pub fn load_config(cmdline_config: &Config) -> Config {
+    let settings = &cmdline_config.settings;
+    let mut config = match settings.no_config {
+        true => Config::default(),
+        false => parse_config(settings.config_file.as_deref()),
     };
+    config.extend(cmdline_config.clone());
+    config
+}
+
+/// Like `read_config`, printing the errors found.
+pub fn parse_config(path: Option<&Path>) -> Config {
+    let config = read_config(path);
+    for error in &config.errors {
+        eprintln!("{}", error);
+    }
+    config
+}
+
+#[cfg(test)]
+fn parse_content(config: &str) -> Config {
+    let mut parser = Parser::default();
+    parser.parse_lines(config, Path::new(CONFIG_FILE), Scope::default());
+    parser.into_config()
+}
+
+/// Reads configuration files, following their `Include` directives.
+#[derive(Default)]
+struct Parser {
+    checks: Vec<Metric>,
+    settings: Settings,
+    errors: Vec<String>,
+    files: Vec<PathBuf>,
+    depth: usize,
+    /// The lines of the `Template`s defined so far, by name.
+    templates: BTreeMap<String, String>,
+    /// Leave `${...}` references as they are, instead of expanding them.
+    raw: bool,
+}
 
-    // Split the content into lines
-    let lines: Vec<&str> = config.trim().lines().collect();
+impl Parser {
+    fn into_config(self) -> Config {
+        Config {
+            checks: self.checks,
+            settings: self.settings,
+            errors: self.errors,
+            files: self.files,
+        }
+    }
 
-    // Iterate over each line and parse it into a Config structure
-    for line in lines {
-        if line.trim_start().starts_with("Group") {
-            if let Some(group_name) = line.split_whitespace().nth(1) {
-                curr_group = group_name;
-            } else {
-                curr_group = "Default"; // Reset to Default if no group name is provided
+    /// Parses a file, starting from the `global` scope. Returns the global scope at
+    /// the end of the file.
+    fn parse_file(&mut self, path: &Path, global: Scope) -> Scope {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            self.errors
+                .push(format!("Too many nested includes at {}", path.display()));
+            return global;
+        }
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                self.errors
+                    .push(format!("Can't read config file {}: {}", path.display(), e));
+                return global;
             }
-            curr_min_value = None; // Reset min/max values for new group
-            curr_max_value = None; // Reset min/max values for new group
-        } else if line.trim_start().starts_with("Min-value") {
-            if let Some(min_val_str) = line.split_whitespace().nth(1) {
-                if let Ok(min_val) = min_val_str.parse::<f64>() {
-                    curr_min_value = Some(min_val);
-                } else {
-                    eprintln!("Failed to parse Min-value in config file at line: {}", line);
-                    curr_min_value = None; // Reset to None if parsing fails
+        };
+        self.files.push(path.to_path_buf());
+        self.depth += 1;
+        let global = if path.extension().is_some_and(|ext| ext == "toml") {
+            self.parse_toml(&content, path, global)
+        } else {
+            self.parse_lines(&content, path, global)
+        };
+        self.depth -= 1;
+        global
+    }
+
+    /// Parses the files matching `pattern`, in alphabetical order. Each file starts
+    /// from the global scope of the including file, and doesn't change it. Errors in
+    /// the pattern are reported at `location`.
+    fn include(&mut self, pattern: &Path, global: &Scope, location: &str) {
+        let paths = match glob::glob(&pattern.to_string_lossy()) {
+            Ok(paths) => paths,
+            Err(e) => {
+                self.errors.push(format!("{}: {}", location, e));
+                return;
+            }
+        };
+        for path in paths {
+            match path {
+                Ok(path) => {
+                    self.parse_file(&path, global.clone());
                 }
-            } else {
-                curr_min_value = None; // Reset to None if no value is provided
+                Err(e) => self.errors.push(format!("{}: {}", location, e)),
+            }
+        }
+    }
+
+    /// Parses a file in the TOML format, see `config::toml`.
+    fn parse_toml(&mut self, content: &str, path: &Path, global: Scope) -> Scope {
+        let document = toml::parse(
+            content,
+            path,
+            global.template(),
+            &mut self.settings,
+            &mut self.errors,
+            self.raw,
+        );
+        self.checks.extend(document.checks);
+        let global = Scope::from_template(&document.global);
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for (pattern, location) in &document.includes {
+            self.include(&dir.join(pattern), &global, location);
+        }
+        global
+    }
+
+    /// Parses the `*.conf` and `*.toml` files of the `<config>.d` directory, if there is one.
+    fn parse_drop_ins(&mut self, config: &Path, global: Scope) {
+        let mut dir = config.as_os_str().to_owned();
+        dir.push(".d");
+        let dir = PathBuf::from(dir);
+        if dir.is_dir() {
+            self.files.push(dir.clone());
+            let mut files: Vec<PathBuf> = ["*.conf", "*.toml"]
+                .iter()
+                .filter_map(|pattern| glob::glob(&dir.join(pattern).to_string_lossy()).ok())
+                .flatten()
+                .filter_map(Result::ok)
+                .collect();
+            files.sort();
+            for file in files {
+                self.parse_file(&file, global.clone());
             }
-        } else if line.trim_start().starts_with("Max-value") {
-            if let Some(max_val_str) = line.split_whitespace().nth(1) {
-                if let Ok(max_val) = max_val_str.parse::<f64>() {
-                    curr_max_value = Some(max_val);
+        }
+    }
+
+    /// Parses the lines of the file at `path`, starting from the `global` scope.
+    /// Returns the global scope at the end.
+    fn parse_lines(&mut self, config: &str, path: &Path, mut global: Scope) -> Scope {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut scope = global.clone();
+        let mut in_group = false;
+
+        // Iterate over each line and parse it into a Config structure
+        for (index, line) in config.lines().enumerate() {
+            let location = format!("{}:{}", path.display(), index + 1);
+            let mut errors = Vec::new();
+            if is_directive(line, "Include") {
+                let pattern = line.trim_start().trim_start_matches("Include").trim();
+                if pattern.is_empty() {
+                    errors.push("Missing Include pattern".to_string());
                 } else {
-                    eprintln!("Failed to parse Max-value in config file at line: {}", line);
-                    curr_max_value = None; // Reset to None if parsing fails
+                    self.include(&dir.join(pattern), &global, &location);
+                }
+            } else if is_directive(line, "Template") {
+                let template = line.trim_start().trim_start_matches("Template").trim();
+                // Checked right away, so that errors point at the template. It's
+                // referred to by its name without `{host}`
+                if let Some(metric) = parse_line(template, &scope, !self.raw, &mut errors) {
+                    let name = metric.short_name.replace("{host}", "");
+                    self.templates.insert(
+                        name.trim_matches(['_', '-']).to_string(),
+                        template.to_string(),
+                    );
+                }
+            } else if is_directive(line, "Hosts") {
+                let mut words = line.split_whitespace().skip(1);
+                let name = words.next().unwrap_or_default();
+                let hosts: Vec<&str> = words.collect();
+                let template = match self.templates.get(name) {
+                    Some(template) => parse_line(template, &scope, !self.raw, &mut errors),
+                    None => {
+                        errors.push(format!("Unknown template '{}'", name));
+                        None
+                    }
+                };
+                if hosts.is_empty() {
+                    errors.push("Missing hosts".to_string());
+                }
+                if let Some(template) = template {
+                    for host in hosts {
+                        self.checks.push(Metric {
+                            source: location.clone(),
+                            ..options::for_host(&template, host)
+                        });
+                    }
                 }
-            } else {
-                curr_max_value = None; // Reset to None if no value is provided
+            } else if is_directive(line, "Group") {
+                // A new group starts from the global settings; the thresholds are always reset
+                scope = global.clone();
+                in_group = true;
+                if let Some(group_name) = line.split_whitespace().nth(1) {
+                    scope.group = group_name.to_string();
+                }
+            } else if is_directive(line, "Min-value") {
+                scope.min_value = threshold(line, "Min-value", &mut errors);
+            } else if is_directive(line, "Max-value") {
+                scope.max_value = threshold(line, "Max-value", &mut errors);
+            } else if is_directive(line, "Warn-min-value") {
+                scope.warn_min_value = threshold(line, "Warn-min-value", &mut errors);
+            } else if is_directive(line, "Warn-max-value") {
+                scope.warn_max_value = threshold(line, "Warn-max-value", &mut errors);
+            } else if is_directive(line, "Every") {
+                let every = line.trim_start().trim_start_matches("Every");
+                match options::parse_schedule(every) {
+                    Ok((every, cron)) => {
+                        scope.every = every;
+                        scope.cron = cron;
+                    }
+                    Err(e) => errors.push(e),
+                }
+                if !in_group {
+                    global.every = scope.every;
+                    global.cron = scope.cron.clone();
+                }
+            } else if is_directive(line, "Tags") {
+                scope.tags = options::list_from_spec(&scope.tags, line.split_whitespace().skip(1));
+                if !in_group {
+                    global.tags = scope.tags.clone();
+                }
+            } else if is_directive(line, "Reporter") {
+                let reporter = line.trim_start().trim_start_matches("Reporter");
+                match options::parse_reporter(reporter) {
+                    Ok(reporter) => scope.reporter = reporter,
+                    Err(e) => errors.push(e),
+                }
+                if !in_group {
+                    global.reporter = scope.reporter.clone();
+                }
+            } else if is_directive(line, "Outputs") {
+                scope.outputs =
+                    options::list_from_spec(&scope.outputs, line.split_whitespace().skip(1));
+                if !in_group {
+                    global.outputs = scope.outputs.clone();
+                    self.settings.outputs = Some(scope.outputs.clone());
+                }
+            } else if is_directive(line, "Catch-up") {
+                match line.split_whitespace().nth(1).map(str::parse::<CatchUp>) {
+                    Some(Ok(catch_up)) => scope.catch_up = catch_up,
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Catch-up policy".to_string()),
+                }
+                if !in_group {
+                    global.catch_up = scope.catch_up;
+                }
+            } else if is_directive(line, "Jitter") {
+                match line.split_whitespace().nth(1).map(options::parse_seconds) {
+                    Some(Ok(jitter)) => scope.jitter = jitter,
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Jitter value".to_string()),
+                }
+                if !in_group {
+                    global.jitter = scope.jitter;
+                }
+            } else if is_directive(line, "Timezone") {
+                match line.split_whitespace().nth(1).map(options::parse_timezone) {
+                    Some(Ok(timezone)) => scope.timezone = timezone,
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Timezone".to_string()),
+                }
+                if !in_group {
+                    global.timezone = scope.timezone;
+                }
+            } else if is_directive(line, "Active") {
+                let window = line.trim_start().trim_start_matches("Active");
+                match options::parse_active(window) {
+                    Ok(active) => scope.active = active,
+                    Err(e) => errors.push(e.to_string()),
+                }
+                if !in_group {
+                    global.active = scope.active.clone();
+                }
+            } else if is_directive(line, "Inactive") {
+                match line.split_whitespace().nth(1).map(options::parse_inactive) {
+                    Some(Ok(inactive_every)) => scope.inactive_every = inactive_every,
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Inactive value".to_string()),
+                }
+                if !in_group {
+                    global.inactive_every = scope.inactive_every;
+                }
+            } else if is_directive(line, "Timeout") {
+                match line.split_whitespace().nth(1).map(options::parse_timeout) {
+                    Some(Ok(timeout)) => scope.timeout = Some(timeout),
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Timeout value".to_string()),
+                }
+                if !in_group {
+                    global.timeout = scope.timeout;
+                }
+            } else if is_directive(line, "Retries") {
+                match line.split_whitespace().nth(1).map(options::parse_retries) {
+                    Some(Ok(retries)) => scope.retries = retries,
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Retries value".to_string()),
+                }
+                if !in_group {
+                    global.retries = scope.retries;
+                }
+            } else if is_directive(line, "Retry-interval") {
+                match line
+                    .split_whitespace()
+                    .nth(1)
+                    .map(options::parse_positive_seconds)
+                {
+                    Some(Ok(interval)) => scope.retry_interval = Some(interval),
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Retry-interval value".to_string()),
+                }
+                if !in_group {
+                    global.retry_interval = scope.retry_interval;
+                }
+            } else if is_directive(line, "Splay") {
+                let mut words = line.split_whitespace().skip(1);
+                match words.next().map(str::parse::<Splay>) {
+                    Some(Ok(splay)) => self.settings.splay = Some(splay),
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Splay mode".to_string()),
+                }
+                match words.next().map(options::parse_seconds) {
+                    Some(Ok(max)) => self.settings.splay_max = Some(max),
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => {}
+                }
+            } else if is_directive(line, "Max-concurrency") {
+                match line.split_whitespace().nth(1).map(str::parse::<usize>) {
+                    Some(Ok(max)) if max > 0 => self.settings.max_concurrency = Some(max),
+                    _ => errors.push("Failed to parse Max-concurrency".to_string()),
+                }
+            } else if is_directive(line, "Shutdown-timeout") {
+                match line.split_whitespace().nth(1).map(options::parse_seconds) {
+                    Some(Ok(timeout)) => self.settings.shutdown_timeout = Some(timeout),
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Shutdown-timeout value".to_string()),
+                }
+            } else if is_directive(line, "Watch-config") {
+                match line
+                    .split_whitespace()
+                    .nth(1)
+                    .map(options::parse_positive_seconds)
+                {
+                    Some(Ok(every)) => self.settings.watch_config = Some(every),
+                    Some(Err(e)) => errors.push(e.to_string()),
+                    None => errors.push("Missing Watch-config value".to_string()),
+                }
+            } else if let Some(config) = parse_line(line, &scope, !self.raw, &mut errors) {
+                self.checks.push(Metric {
+                    source: location.clone(),
+                    ..config
+                });
             }
-        } else if let Some(config) = parse_line(line, curr_group, curr_min_value, curr_max_value) {
-            configs.push(config);
+            self.errors
+                .extend(errors.into_iter().map(|e| format!("{}: {}", location, e)));
         }
+        global
     }
-    configs
 }
 
 #[test]
@@ -140,7 +717,7 @@ test10::100::load_avg::localhost
     // Go to the temp directory and load the configuration.
     use std::env;
     let _ = env::set_current_dir(&temp_dir);
-    let configs = parse_config();
+    let configs = parse_config(None).checks;
 
     assert_eq!(configs.len(), 10);
 
@@ -148,6 +725,7 @@ test10::100::load_avg::localhost
     assert_eq!(configs[0].function, "load_avg");
     assert_eq!(configs[0].args, "localhost");
     assert_eq!(configs[0].short_name, "test1");
+    assert_eq!(configs[0].source, "./jr.conf:5");
     assert_eq!(configs[0].group, "group1");
     assert_eq!(configs[0].min_value, Some(1.0));
     assert_eq!(configs[0].max_value, Some(100.0));
@@ -218,3 +796,497 @@ test10::100::load_avg::localhost
 
     temp_dir.close().unwrap();
 }
+
+#[test]
+fn test_parse_outputs() {
+    let config_content = r#"
+Outputs -graphite
+test1::10::load_avg::localhost
+
+Group group1
+Outputs stdout
+test2::10::load_avg::localhost
+test3;outputs=+angelweb::10::load_avg::localhost
+
+Group group2
+test4;outputs=none::10::load_avg::localhost
+test5;outputs::10::load_avg::localhost
+"#;
+
+    let configs = parse_content(config_content).checks;
+    assert_eq!(configs.len(), 4);
+    assert_eq!(configs[0].short_name, "test1");
+    assert_eq!(configs[0].outputs, vec!["stdout", "angelweb"]);
+    assert_eq!(configs[1].outputs, vec!["stdout"]);
+    assert_eq!(configs[2].short_name, "test3");
+    assert_eq!(configs[2].outputs, vec!["stdout", "angelweb"]);
+    // group2 starts again from the global outputs
+    assert_eq!(configs[3].short_name, "test4");
+    assert!(configs[3].outputs.is_empty());
+}
+
+#[test]
+fn test_convert_to_toml() {
+    let dir = tempfile::tempdir().unwrap();
+    let conf = dir.path().join("jr.conf");
+    fs::write(
+        &conf,
+        "Max-concurrency 2\n\
+         Splay hash 30\n\
+         early::10::load_avg::localhost\n\
+         Outputs stdout statsd\n\
+         late::10::load_avg::localhost\n\
+         quiet;outputs=none::10::load_avg::localhost\n\
+         Group office\n\
+         Outputs +angelweb\n\
+         web::60::check_url::https://example.com\n",
+    )
+    .unwrap();
+    let config = read_config(Some(&conf));
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let path = dir.path().join("jr.toml");
+    fs::write(&path, toml::to_toml(&config)).unwrap();
+    let converted = read_config(Some(&path));
+    assert!(converted.errors.is_empty(), "{:?}", converted.errors);
+    assert_eq!(converted.settings, config.settings);
+    assert_eq!(
+        converted.settings.outputs,
+        Some(vec!["stdout".to_string(), "statsd".to_string()])
+    );
+    assert_eq!(converted.checks.len(), config.checks.len());
+    for (converted, original) in converted.checks.iter().zip(&config.checks) {
+        assert!(converted.same_config(original), "{}", original.short_name);
+        assert_eq!(converted.outputs, original.outputs);
+    }
+}
+
+#[test]
+fn test_parse_max_concurrency() {
+    let config = parse_content("Max-concurrency 3\ntest1::10::load_avg::localhost\n");
+    assert_eq!(config.settings.max_concurrency, Some(3));
+    assert_eq!(config.checks.len(), 1);
+
+    let config = parse_content("Max-concurrency 0\n");
+    assert_eq!(config.settings.max_concurrency, None);
+}
+
+#[test]
+fn test_parse_checks_named_like_directives() {
+    let config_content = r#"
+Active_users::60::load_avg::localhost
+Timeout_probe::60::load_avg::localhost
+Outputs-check::60::load_avg::localhost
+  Timeout 30
+Group::60::load_avg::localhost
+"#;
+
+    let config = parse_content(config_content);
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    let names: Vec<&str> = config
+        .checks
+        .iter()
+        .map(|c| c.short_name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        vec!["Active_users", "Timeout_probe", "Outputs-check", "Group"]
+    );
+    assert_eq!(config.checks[3].timeout, Some(Duration::from_secs(30)));
+}
+
+#[test]
+fn test_parse_includes_and_drop_ins() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, content: &str| {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    write(
+        "jr.conf",
+        "Outputs stdout\nInclude checks/*.conf\nmain::10::load_avg::localhost\n",
+    );
+    write("checks/a.conf", "Group teamA\na::10::load_avg::localhost\n");
+    write("checks/b.conf", "b::10::load_avg::localhost\n");
+    write("jr.conf.d/c.conf", "c::10::load_avg::localhost\n");
+    write("jr.conf.d/ignored.txt", "d::10::load_avg::localhost\n");
+
+    let config = parse_config(Some(&dir.path().join("jr.conf")));
+    assert!(config.errors.is_empty());
+    let names: Vec<&str> = config
+        .checks
+        .iter()
+        .map(|c| c.short_name.as_str())
+        .collect();
+    assert_eq!(names, vec!["a", "b", "main", "c"]);
+    // Groups don't leak out of included files, global settings go in
+    let groups: Vec<&str> = config.checks.iter().map(|c| c.group.as_str()).collect();
+    assert_eq!(groups, vec!["teamA", "Default", "Default", "Default"]);
+    assert!(config.checks.iter().all(|c| c.outputs == vec!["stdout"]));
+    assert_eq!(config.files.len(), 5);
+
+    write("loop.conf", "Include loop.conf\n");
+    let config = parse_config(Some(&dir.path().join("loop.conf")));
+    assert_eq!(config.errors.len(), 1);
+    assert!(config.errors[0].starts_with("Too many nested includes"));
+
+    let config = parse_config(Some(&dir.path().join("missing.conf")));
+    assert!(config.errors[0].starts_with("Can't read config file"));
+}
+
+#[test]
+fn test_parse_errors() {
+    let config_content = r#"
+Max-value lots
+Watch-config 2
+test1::10::load_avg::localhost
+test2::ten::load_avg::localhost
+test3;color=blue::10::load_avg::localhost
+not a check
+"#;
+
+    let config = parse_content(config_content);
+    assert_eq!(config.checks.len(), 1);
+    assert_eq!(config.settings.watch_config, Some(Duration::from_secs(2)));
+    assert_eq!(
+        config.errors,
+        vec![
+            "jr.conf:2: Failed to parse Max-value",
+            "jr.conf:5: Failed to parse N value",
+            "jr.conf:6: Unknown option 'color'",
+            "jr.conf:7: Invalid format, expected name::seconds::worker::args",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_directive_errors() {
+    let config_content = r#"
+Timeout abc
+Timezone Mars/Olympus
+Timezone
+Catch-up sometimes
+Group web
+Active Mon-Fri 25:00-26:00
+Splay hash forever
+test1::10::load_avg::localhost
+"#;
+
+    let config = parse_content(config_content);
+    assert_eq!(config.checks.len(), 1);
+    assert_eq!(config.errors.len(), 6, "{:?}", config.errors);
+    for (error, line) in config.errors.iter().zip([2, 3, 4, 5, 7, 8]) {
+        let location = format!("jr.conf:{}: ", line);
+        assert!(error.starts_with(&location), "{}", error);
+    }
+    assert_eq!(
+        config.errors[0],
+        "jr.conf:2: Invalid number of seconds 'abc'"
+    );
+    assert_eq!(config.errors[2], "jr.conf:4: Missing Timezone");
+}
+
+#[test]
+fn test_parse_shutdown_timeout() {
+    let config = parse_content("Shutdown-timeout 2.5\ntest1::10::load_avg::localhost\n");
+    assert_eq!(
+        config.settings.shutdown_timeout,
+        Some(Duration::from_millis(2500))
+    );
+    assert_eq!(config.checks.len(), 1);
+
+    let config = parse_content("Shutdown-timeout soon\n");
+    assert_eq!(config.settings.shutdown_timeout, None);
+}
+
+#[test]
+fn test_parse_catch_up() {
+    let config_content = r#"
+Catch-up skip
+test1::10::load_avg::localhost
+test2;catch_up=all::10::load_avg::localhost
+
+Group group1
+test3::10::load_avg::localhost
+Catch-up once
+test4::10::load_avg::localhost
+test5;catch_up=never::10::load_avg::localhost
+"#;
+
+    let configs = parse_content(config_content).checks;
+    assert_eq!(configs.len(), 4);
+    assert_eq!(configs[0].catch_up, CatchUp::Skip);
+    assert_eq!(configs[1].catch_up, CatchUp::All);
+    assert_eq!(configs[2].catch_up, CatchUp::Skip);
+    assert_eq!(configs[3].catch_up, CatchUp::Once);
+}
+
+#[test]
+fn test_parse_splay_and_jitter() {
+    let config_content = r#"
+Splay hash 30
+Jitter 2
+test1::10::load_avg::localhost
+
+Group group1
+Jitter 0.5
+test2::10::load_avg::localhost
+test3;jitter=5::10::load_avg::localhost
+"#;
+
+    let config = parse_content(config_content);
+    assert_eq!(config.settings.splay, Some(Splay::Hash));
+    assert_eq!(config.settings.splay_max, Some(Duration::from_secs(30)));
+    assert_eq!(config.checks[0].jitter, Duration::from_secs(2));
+    assert_eq!(config.checks[1].jitter, Duration::from_millis(500));
+    assert_eq!(config.checks[2].jitter, Duration::from_secs(5));
+}
+
+#[test]
+fn test_parse_cron_and_windows() {
+    let config_content = r#"
+Timezone America/Argentina/Buenos_Aires
+saltogrande_gefs_00::15 0,6 * * *::timethis::sleep 3
+daily;tz=UTC::@daily::load_avg::localhost
+
+Group office
+Active Mon-Fri 09:00-18:00
+web::60::check_url::https://example.com
+web_nights;inactive=600::60::check_url::https://example.com
+always;active=always::60::check_url::https://example.com
+bad::15 25 * * *::timethis::sleep 3
+"#;
+
+    let configs = parse_content(config_content).checks;
+    assert_eq!(configs.len(), 5);
+    assert!(configs[0].cron.is_some());
+    assert_eq!(configs[0].n, 6 * 3600);
+    assert_eq!(configs[0].args, "sleep 3");
+    assert_eq!(configs[0].timezone.name(), "America/Argentina/Buenos_Aires");
+    assert_eq!(configs[1].timezone, Tz::UTC);
+    assert_eq!(configs[1].n, 24 * 3600);
+
+    assert_eq!(configs[2].timezone.name(), "America/Argentina/Buenos_Aires");
+    assert_eq!(
+        configs[2].active,
+        Some("Mon-Fri 09:00-18:00".parse().unwrap())
+    );
+    assert_eq!(configs[2].inactive_every, None);
+    assert_eq!(configs[3].inactive_every, Some(600));
+    assert_eq!(configs[4].active, None);
+}
+
+#[test]
+fn test_parse_timeout() {
+    let config_content = r#"
+test1::10::load_avg::localhost
+Timeout 30
+test2::10::load_avg::localhost
+
+Group group1
+Timeout 5
+test3::10::load_avg::localhost
+test4;timeout=0.5::10::load_avg::localhost
+test5;timeout=0::10::load_avg::localhost
+
+Group group2
+test6::10::load_avg::localhost
+"#;
+
+    let configs = parse_content(config_content).checks;
+    assert_eq!(configs.len(), 5);
+    assert_eq!(configs[0].timeout, None);
+    assert_eq!(configs[1].timeout, Some(Duration::from_secs(30)));
+    assert_eq!(configs[2].timeout, Some(Duration::from_secs(5)));
+    assert_eq!(configs[3].timeout, Some(Duration::from_millis(500)));
+    assert_eq!(configs[4].short_name, "test6");
+    assert_eq!(configs[4].timeout, Some(Duration::from_secs(30)));
+}
+
+#[test]
+fn test_parse_retries() {
+    let config_content = r#"
+Retries 2
+test1::10::check_url::https://example.com
+test2;retries=0::10::check_url::https://example.com
+
+Group group1
+Retries 4
+Retry-interval 1.5
+test3::10::check_url::https://example.com
+test4;retry_interval=3::10::check_url::https://example.com
+test5;retries=many::10::check_url::https://example.com
+"#;
+
+    let configs = parse_content(config_content).checks;
+    assert_eq!(configs.len(), 4);
+    assert_eq!((configs[0].retries, configs[0].retry_interval), (2, None));
+    assert_eq!(configs[1].retries, 0);
+    assert_eq!(configs[2].retries, 4);
+    assert_eq!(configs[2].retry_interval, Some(Duration::from_millis(1500)));
+    assert_eq!(configs[3].retry_interval, Some(Duration::from_secs(3)));
+}
+
+#[test]
+fn test_parse_interpolation() {
+    env::set_var("JR_TEST_API_TOKEN", "s3cr3t");
+    env::set_var("JR_TEST_DIRECTIVE", "Timeout 1");
+    let config_content = r#"
+# ${NOT_EXPANDED_IN_COMMENTS}
+api;timeout=${JR_TEST_TIMEOUT:-5}::60::query_api::url=https://api.example.com/?token=${JR_TEST_API_TOKEN} jq=.value
+price::60::runthis::echo $${PRICE}
+broken::60::runthis::echo ${JR_TEST_UNDEFINED}
+${JR_TEST_DIRECTIVE}
+"#;
+    let config = parse_content(config_content);
+    assert_eq!(
+        config.errors,
+        vec![
+            "jr.conf:5: Undefined variable 'JR_TEST_UNDEFINED'",
+            "jr.conf:6: Invalid format, expected name::seconds::worker::args",
+        ]
+    );
+    assert_eq!(config.checks.len(), 2);
+    let api = &config.checks[0];
+    assert_eq!(api.timeout, Some(Duration::from_secs(5)));
+    assert_eq!(
+        api.args,
+        "url=https://api.example.com/?token=s3cr3t jq=.value"
+    );
+    assert_eq!(api.secrets, vec!["s3cr3t"]);
+    assert_eq!(
+        api.redact(&api.args),
+        "url=https://api.example.com/?token=*** jq=.value"
+    );
+    assert_eq!(config.checks[1].args, "echo ${PRICE}");
+}
+
+#[test]
+fn test_parse_interpolation_after_split() {
+    env::set_var("JR_TEST_DB_PASSWORD", "pa::ss;timeout=1::runthis::x");
+    let config = parse_content(
+        "db;tags=${JR_TEST_DB_PASSWORD}::60::runthis::login ${JR_TEST_DB_PASSWORD}\n",
+    );
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    let db = &config.checks[0];
+    assert_eq!(
+        (db.short_name.as_str(), db.n, db.function.as_str()),
+        ("db", 60, "runthis")
+    );
+    assert_eq!(db.timeout, None);
+    assert_eq!(db.tags, vec!["pa::ss;timeout=1::runthis::x"]);
+    assert_eq!(db.redact(&db.args), "login ***");
+
+    let check = parse_check("db::60::runthis::login ${JR_TEST_DB_PASSWORD}").unwrap();
+    assert_eq!(check.function, "runthis");
+    assert_eq!(check.args, "login pa::ss;timeout=1::runthis::x");
+    assert_eq!(check.secrets, vec!["pa::ss;timeout=1::runthis::x"]);
+}
+
+#[test]
+fn test_parse_templates() {
+    let config_content = r#"
+Template web_up;timeout=5::60::check_url::https://{host}/health
+Template {host}_load::30::load_avg::{host}
+Group frontend
+Hosts web_up web1.example.com web2.example.com
+Group backend
+Hosts web_up api
+Hosts load db-1
+Hosts nope host1
+Template broken::sixty::check_url::https://{host}/
+"#;
+    let config = parse_content(config_content);
+    assert_eq!(
+        config.errors,
+        vec![
+            "jr.conf:9: Unknown template 'nope'",
+            "jr.conf:10: Failed to parse N value",
+        ]
+    );
+    let names: Vec<&str> = config
+        .checks
+        .iter()
+        .map(|c| c.short_name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "web_up_web1_example_com",
+            "web_up_web2_example_com",
+            "web_up_api",
+            "db-1_load",
+        ]
+    );
+    let web1 = &config.checks[0];
+    assert_eq!(web1.args, "https://web1.example.com/health");
+    assert_eq!(web1.group, "frontend");
+    assert_eq!(web1.timeout, Some(Duration::from_secs(5)));
+    assert_eq!(web1.source, "jr.conf:5");
+    assert_eq!(config.checks[2].group, "backend");
+    assert_eq!(config.checks[3].args, "db-1");
+}
+
+#[test]
+fn test_parse_group_settings() {
+    let config_content = r#"
+Tags site=mordor
+Group Mordor
+Every 30
+Timeout 10
+Retries 2
+Tags +team=ops
+Reporter jr@barad-dur
+Outputs stdout
+load::::load_avg::mordor
+disk;tags=none;reporter=jr@orodruin::300::df::/
+Group Gondor
+web::::check_url::https://gondor.example.com
+"#;
+    let config = parse_content(config_content);
+    assert_eq!(config.errors, vec!["jr.conf:13: Failed to parse N value"]);
+    assert_eq!(config.checks.len(), 2);
+
+    let load = &config.checks[0];
+    assert_eq!(load.n, 30);
+    assert_eq!(load.timeout, Some(Duration::from_secs(10)));
+    assert_eq!(load.retries, 2);
+    assert_eq!(load.tags, vec!["site=mordor", "team=ops"]);
+    assert_eq!(load.reporter, "jr@barad-dur");
+    assert_eq!(load.outputs, vec!["stdout"]);
+
+    let disk = &config.checks[1];
+    assert_eq!(disk.n, 300);
+    assert!(disk.tags.is_empty());
+    assert_eq!(disk.reporter, "jr@orodruin");
+    assert_eq!(disk.retries, 2);
+}
+
+#[test]
+fn test_parse_thresholds() {
+    let config_content = r#"
+Group Disks
+Max-value 95
+Warn-max-value 80
+root::300::df::/
+data;warn_max_value=90;max_value=98::300::df::/data
+Warn-min-value low
+Group Other
+web::60::check_url::https://example.com
+"#;
+    let config = parse_content(config_content);
+    assert_eq!(
+        config.errors,
+        vec!["jr.conf:7: Failed to parse Warn-min-value"]
+    );
+    let checks = &config.checks;
+    assert_eq!(checks[0].max_value, Some(95.0));
+    assert_eq!(checks[0].warn_max_value, Some(80.0));
+    assert_eq!(checks[1].max_value, Some(98.0));
+    assert_eq!(checks[1].warn_max_value, Some(90.0));
+    assert_eq!(
+        (checks[2].max_value, checks[2].warn_max_value),
+        (None, None)
+    );
+}
@@ -0,0 +1,187 @@
+// Expansion of `${...}` references in configuration values:
+//
+//     ${VAR}            the environment variable VAR, which must be set
+//     ${VAR:-default}   VAR, or `default` if it's unset or empty
+//     ${secret:VAR}     VAR, taken as a secret, also with a default
+//     ${file:/path}     the content of a file, without its trailing newlines, as
+//                       with Docker and Kubernetes secrets
+//     $${               a literal `${`
+//
+// The values read from files, and from the variables marked as secrets either by
+// `secret:` or by their name, are kept with the check so that they can be
+// redacted whenever its args are printed. Other variables, like a port or a host
+// name, are printed as they are.
+use std::env;
+use std::fs;
+
+/// Variables ending with these are secrets without `secret:`.
+const SECRET_SUFFIXES: [&str; 3] = ["_TOKEN", "_SECRET", "_PASSWORD"];
+
+/// Secrets shorter than this aren't redacted, as `***` would replace unrelated
+/// text wherever they appear.
+const MIN_SECRET_LEN: usize = 4;
+
+/// Expands the references in `text`. The values substituted are added to `secrets`.
+pub fn expand(text: &str, secrets: &mut Vec<String>) -> Result<String, String> {
+    expand_with(text, secrets, |name| env::var(name).ok())
+}
+
+fn expand_with(
+    text: &str,
+    secrets: &mut Vec<String>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            // `$${`: the first `$` stands for the one of the literal `${`
+            expanded.push_str(&rest[..start]);
+            expanded.push('{');
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("Unterminated reference '{}'", &rest[start..]));
+        };
+        let reference = &rest[start + 2..start + end];
+        let value = resolve(reference, &lookup)?;
+        if let Some(secret) = value.secret {
+            if secret.chars().count() >= MIN_SECRET_LEN && !secrets.contains(&secret) {
+                secrets.push(secret);
+            }
+        }
+        expanded.push_str(&value.text);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+struct Value {
+    text: String,
+    /// The value itself if it's a secret, unless it's a default from the configuration.
+    secret: Option<String>,
+}
+
+fn resolve(reference: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<Value, String> {
+    if let Some(path) = reference.strip_prefix("file:") {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Can't read secret file {}: {}", path, e))?;
+        let text = content.trim_end_matches(['\n', '\r']).to_string();
+        return Ok(Value {
+            secret: Some(text.clone()),
+            text,
+        });
+    }
+    let (marked, reference) = match reference.strip_prefix("secret:") {
+        Some(reference) => (true, reference),
+        None => (false, reference),
+    };
+    let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid variable name '{}'", name));
+    }
+    let secret = marked || SECRET_SUFFIXES.iter().any(|suffix| name.ends_with(suffix));
+    match (lookup(name), default) {
+        (Some(value), None) => Ok(Value {
+            secret: secret.then(|| value.clone()),
+            text: value,
+        }),
+        (Some(value), Some(_)) if !value.is_empty() => Ok(Value {
+            secret: secret.then(|| value.clone()),
+            text: value,
+        }),
+        (_, Some(default)) => Ok(Value {
+            text: default.to_string(),
+            secret: None,
+        }),
+        (None, None) => Err(format!("Undefined variable '{}'", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "API_TOKEN" | "TOKEN" => Some("s3cr3t".to_string()),
+            "PORT" => Some("8080".to_string()),
+            "PIN_PASSWORD" => Some("123".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut secrets = Vec::new();
+        let expand = |text: &str, secrets: &mut Vec<String>| expand_with(text, secrets, lookup);
+
+        assert_eq!(
+            expand("url=https://api/?t=${API_TOKEN} jq=.a", &mut secrets).unwrap(),
+            "url=https://api/?t=s3cr3t jq=.a"
+        );
+        assert_eq!(secrets, vec!["s3cr3t"]);
+
+        // Only variables marked as secrets are, and not when they're too short
+        assert_eq!(
+            expand("localhost:${PORT} ${PIN_PASSWORD}", &mut secrets).unwrap(),
+            "localhost:8080 123"
+        );
+        assert_eq!(secrets, vec!["s3cr3t"]);
+        let mut marked = Vec::new();
+        assert_eq!(
+            expand("${secret:TOKEN} ${secret:PORT:-80}", &mut marked).unwrap(),
+            "s3cr3t 8080"
+        );
+        assert_eq!(marked, vec!["s3cr3t", "8080"]);
+
+        assert_eq!(
+            expand("${HOST:-localhost} ${EMPTY:-none} ${EMPTY}", &mut secrets).unwrap(),
+            "localhost none "
+        );
+        assert_eq!(
+            expand("echo $HOME $${API_TOKEN}", &mut secrets).unwrap(),
+            "echo $HOME ${API_TOKEN}"
+        );
+        assert_eq!(secrets, vec!["s3cr3t"]);
+
+        assert_eq!(
+            expand("${MISSING}", &mut secrets),
+            Err("Undefined variable 'MISSING'".to_string())
+        );
+        assert_eq!(
+            expand("${API_TOKEN", &mut secrets),
+            Err("Unterminated reference '${API_TOKEN'".to_string())
+        );
+        assert_eq!(
+            expand("${A B}", &mut secrets),
+            Err("Invalid variable name 'A B'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expand_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "from-file\n").unwrap();
+        let mut secrets = Vec::new();
+        let text = format!("Bearer ${{file:{}}}", path.display());
+        assert_eq!(
+            expand_with(&text, &mut secrets, lookup).unwrap(),
+            "Bearer from-file"
+        );
+        assert_eq!(secrets, vec!["from-file"]);
+
+        let text = format!("${{file:{}}}", dir.path().join("missing").display());
+        assert!(expand_with(&text, &mut secrets, lookup)
+            .unwrap_err()
+            .starts_with("Can't read secret file"));
+    }
+}
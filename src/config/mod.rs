@@ -1,2 +1,5 @@
 pub mod cmdline;
 pub mod file;
+pub mod interpolate;
+pub mod options;
+pub mod toml;
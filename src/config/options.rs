@@ -0,0 +1,278 @@
+// Per-check options, written after the check name in jr.conf:
+// `name;key=value;key=value::seconds::test::arguments`
+use std::time::Duration;
+
+use chrono_tz::Tz;
+
+use crate::scheduler::calendar::{Cron, Window};
+use crate::types::Metric;
+
+pub type CheckOptions<'a> = Vec<(&'a str, &'a str)>;
+
+/// Splits the name field of a check line into the name and its `key=value` options.
+pub fn split_name(field: &str) -> Result<(&str, CheckOptions<'_>), String> {
+    let mut parts = field.split(';');
+    let name = parts.next().unwrap_or_default().trim();
+    let mut options = Vec::new();
+    for option in parts.map(str::trim).filter(|o| !o.is_empty()) {
+        match option.split_once('=') {
+            Some((key, value)) => options.push((key.trim(), value.trim())),
+            None => return Err(format!("Option '{}' is not in key=value form", option)),
+        }
+    }
+    Ok((name, options))
+}
+
+/// Applies a single option to `metric`.
+pub fn apply(metric: &mut Metric, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "every" => (metric.n, metric.cron) = parse_schedule(value)?,
+        "outputs" => metric.outputs = list_from_spec(&metric.outputs, value.split(',')),
+        "tags" => metric.tags = list_from_spec(&metric.tags, value.split(',')),
+        "reporter" => metric.reporter = parse_reporter(value)?,
+        "catch_up" => metric.catch_up = value.parse()?,
+        "jitter" => metric.jitter = parse_seconds(value)?,
+        "timeout" => metric.timeout = Some(parse_timeout(value)?),
+        "retries" => metric.retries = parse_retries(value)?,
+        "retry_interval" => metric.retry_interval = Some(parse_positive_seconds(value)?),
+        "tz" => metric.timezone = parse_timezone(value)?,
+        "active" => metric.active = parse_active(value)?,
+        "inactive" => metric.inactive_every = parse_inactive(value)?,
+        "min_value" => metric.min_value = Some(parse_value(value)?),
+        "max_value" => metric.max_value = Some(parse_value(value)?),
+        "warn_min_value" => metric.warn_min_value = Some(parse_value(value)?),
+        "warn_max_value" => metric.warn_max_value = Some(parse_value(value)?),
+        _ => return Err(format!("Unknown option '{}'", key)),
+    }
+    Ok(())
+}
+
+/// Parses the `seconds` field: a number of seconds or a cron expression. For cron
+/// expressions, `n` is the approximate interval between runs.
+pub fn parse_schedule(field: &str) -> Result<(u64, Option<Cron>), String> {
+    match field.trim().parse::<u64>() {
+        Ok(0) => Err("Invalid N value".to_string()),
+        Ok(n) => Ok((n, None)),
+        Err(_) if field.trim().contains(' ') || field.trim().starts_with('@') => {
+            let cron = Cron::parse(field)?;
+            Ok((cron.period().as_secs().max(1), Some(cron)))
+        }
+        Err(_) => Err("Failed to parse N value".to_string()),
+    }
+}
+
+pub fn parse_value(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}'", value))
+}
+
+/// Parses a number of seconds, which may have a fractional part.
+pub fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("Invalid number of seconds '{}'", value))
+}
+
+/// Parses a span of time like `90s`, `30m`, `1h` or `2d`. Plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let seconds: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("Invalid duration '{}'", value)),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Ok(Duration::from_secs_f64(n * seconds as f64)),
+        _ => Err(format!("Invalid duration '{}'", value)),
+    }
+}
+
+/// Parses a timeout, which can't be zero.
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    match parse_seconds(value)? {
+        timeout if timeout.is_zero() => Err("The timeout can't be zero".to_string()),
+        timeout => Ok(timeout),
+    }
+}
+
+/// Parses a number of seconds that can't be zero, like a retry interval.
+pub fn parse_positive_seconds(value: &str) -> Result<Duration, String> {
+    match parse_seconds(value)? {
+        secs if secs.is_zero() => Err(format!("Invalid duration '{}'", value)),
+        secs => Ok(secs),
+    }
+}
+
+pub fn parse_retries(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid number of retries '{}'", value))
+}
+
+pub fn parse_timezone(value: &str) -> Result<Tz, String> {
+    value
+        .parse::<Tz>()
+        .map_err(|_| format!("Unknown timezone '{}'", value))
+}
+
+/// Parses an active window. `always` (or nothing) means no window.
+pub fn parse_active(value: &str) -> Result<Option<Window>, String> {
+    match value.trim() {
+        "" | "always" => Ok(None),
+        window => window.parse().map(Some),
+    }
+}
+
+/// Parses the interval outside the active window. `off` means the check doesn't run.
+pub fn parse_inactive(value: &str) -> Result<Option<u64>, String> {
+    match value.trim() {
+        "off" => Ok(None),
+        secs => match secs.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(format!("Invalid inactive interval '{}'", secs)),
+        },
+    }
+}
+
+pub fn parse_reporter(value: &str) -> Result<String, String> {
+    match value.trim() {
+        "" => Err("Missing reporter".to_string()),
+        reporter => Ok(reporter.to_string()),
+    }
+}
+
+/// Computes a list, like the outputs or the tags, from the inherited one. Plain names
+/// replace the inherited list, `+name` adds an item, `-name` removes one and `none`
+/// clears the list.
+pub fn list_from_spec<'a>(
+    current: &[String],
+    spec: impl IntoIterator<Item = &'a str>,
+) -> Vec<String> {
+    let mut outputs = current.to_vec();
+    let mut replaced = false;
+    for item in spec.into_iter().map(str::trim).filter(|i| !i.is_empty()) {
+        if item == "none" {
+            outputs.clear();
+            replaced = true;
+        } else if let Some(name) = item.strip_prefix('-') {
+            outputs.retain(|o| o != name);
+        } else {
+            let name = item.strip_prefix('+').unwrap_or(item);
+            if !item.starts_with('+') && !replaced {
+                outputs.clear();
+                replaced = true;
+            }
+            if !outputs.iter().any(|o| o == name) {
+                outputs.push(name.to_string());
+            }
+        }
+    }
+    outputs
+}
+
+/// The check for `host` made from the template `metric`. `{host}` is replaced in the
+/// args, and in the name if it's there; otherwise the host is appended to the name.
+/// In names, characters outputs use as separators, like dots, become `_`.
+pub fn for_host(metric: &Metric, host: &str) -> Metric {
+    let name_host: String = host
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    let short_name = match metric.short_name.contains("{host}") {
+        true => metric.short_name.replace("{host}", &name_host),
+        false => format!("{}_{}", metric.short_name, name_host),
+    };
+    Metric {
+        short_name,
+        args: metric.args.replace("{host}", host),
+        ..metric.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_list_from_spec() {
+        let current = names(&["stdout", "graphite", "angelweb"]);
+        assert_eq!(
+            list_from_spec(&current, ["-graphite"]),
+            names(&["stdout", "angelweb"])
+        );
+        assert_eq!(
+            list_from_spec(&current, ["stdout", "angelweb"]),
+            names(&["stdout", "angelweb"])
+        );
+        assert_eq!(
+            list_from_spec(&names(&["stdout"]), ["+angelweb", "+stdout"]),
+            names(&["stdout", "angelweb"])
+        );
+        assert!(list_from_spec(&current, ["none"]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_seconds() {
+        assert_eq!(parse_seconds("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_seconds("0.5"), Ok(Duration::from_millis(500)));
+        assert!(parse_seconds("-1").is_err());
+        assert!(parse_seconds("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn test_split_name() {
+        assert_eq!(split_name("test1").unwrap(), ("test1", vec![]));
+        assert_eq!(
+            split_name("test1;outputs=stdout,-graphite").unwrap(),
+            ("test1", vec![("outputs", "stdout,-graphite")])
+        );
+        assert!(split_name("test1;outputs").is_err());
+    }
+
+    #[test]
+    fn test_for_host() {
+        let template = Metric {
+            short_name: "web_up".to_string(),
+            args: "https://{host}/health".to_string(),
+            ..Default::default()
+        };
+        let check = for_host(&template, "web1.example.com");
+        assert_eq!(check.short_name, "web_up_web1_example_com");
+        assert_eq!(check.args, "https://web1.example.com/health");
+
+        let template = Metric {
+            short_name: "{host}_load".to_string(),
+            ..template
+        };
+        assert_eq!(for_host(&template, "db-1").short_name, "db-1_load");
+    }
+}
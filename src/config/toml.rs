@@ -0,0 +1,665 @@
+// The TOML configuration format, an alternative to the `::` lines of jr.conf for
+// files ending in `.toml`:
+//
+//     max_concurrency = 8
+//     outputs = ["stdout", "angelweb"]
+//
+//     [[check]]
+//     name = "load_avg"
+//     every = 30
+//     worker = "load_avg"
+//     args = "mordor"
+//
+//     [group.office]
+//     tz = "Europe/Madrid"
+//     active = "Mon-Fri 09:00-18:00"
+//
+//     [[group.office.check]]
+//     name = "web"
+//     every = "*/5 * * * *"
+//     worker = "check_url"
+//     args = "https://intranet.example.com"
+//     timeout = 5
+//
+// Besides `name`, `every`, `worker` and `args`, checks, groups and the top level
+// take the same options as the name field of a jr.conf line.
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono_tz::Tz;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, ImDocument, Item, Table, Value};
+
+use crate::config::{interpolate, options};
+use crate::types::{CatchUp, Config, Metric, Settings};
+
+/// The checks of a TOML file, the settings of its top level and the patterns it includes.
+pub struct Document {
+    pub checks: Vec<Metric>,
+    pub global: Metric,
+    /// Each pattern, with the `file:line` it's included at.
+    pub includes: Vec<(String, String)>,
+}
+
+/// Parses the TOML file at `path`. Checks start from `global`, which the top level
+/// of the file can change for its own checks. `${...}` references in the args and
+/// options of checks are expanded unless `raw`.
+pub fn parse(
+    content: &str,
+    path: &Path,
+    global: Metric,
+    settings: &mut Settings,
+    errors: &mut Vec<String>,
+    raw: bool,
+) -> Document {
+    let mut parser = TomlParser {
+        content,
+        path,
+        errors,
+        raw,
+    };
+    let mut document = Document {
+        checks: Vec::new(),
+        global,
+        includes: Vec::new(),
+    };
+    let doc = match ImDocument::parse(content) {
+        Ok(doc) => doc,
+        Err(e) => {
+            parser.error(e.span(), e.message().to_string());
+            return document;
+        }
+    };
+
+    let root = doc.as_table();
+    for (key, item) in root.iter() {
+        match key {
+            "check" | "group" => {}
+            "include" => {
+                let location = parser.location(item.span());
+                document.includes = parser
+                    .strings(item)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|pattern| (pattern, location.clone()))
+                    .collect();
+            }
+            "max_concurrency" | "splay" | "splay_max" | "shutdown_timeout" | "watch_config" => {
+                parser.setting(settings, key, item)
+            }
+            _ => {
+                parser.option(&mut document.global, key, item);
+            }
+        }
+    }
+    if root.contains_key("outputs") {
+        settings.outputs = Some(document.global.outputs.clone());
+    }
+    if let Some(checks) = root.get("check") {
+        parser.checks(checks, &document.global, &mut document.checks);
+    }
+    if let Some(groups) = root.get("group") {
+        let Some(groups) = groups.as_table() else {
+            parser.error(groups.span(), "group must be a table".to_string());
+            return document;
+        };
+        for (name, group) in groups.iter() {
+            let Some(table) = group.as_table() else {
+                parser.error(group.span(), format!("group.{} must be a table", name));
+                continue;
+            };
+            let mut template = Metric {
+                group: name.to_string(),
+                ..document.global.clone()
+            };
+            for (key, item) in table.iter() {
+                if key != "check" {
+                    parser.option(&mut template, key, item);
+                }
+            }
+            if let Some(checks) = table.get("check") {
+                parser.checks(checks, &template, &mut document.checks);
+            }
+        }
+    }
+    document
+}
+
+struct TomlParser<'a> {
+    content: &'a str,
+    path: &'a Path,
+    errors: &'a mut Vec<String>,
+    raw: bool,
+}
+
+impl TomlParser<'_> {
+    /// The `file:line` of `span`.
+    fn location(&self, span: Option<Range<usize>>) -> String {
+        let offset = span.map(|s| s.start).unwrap_or_default();
+        let line = self.content[..offset.min(self.content.len())]
+            .matches('\n')
+            .count()
+            + 1;
+        format!("{}:{}", self.path.display(), line)
+    }
+
+    fn error(&mut self, span: Option<Range<usize>>, message: String) {
+        let location = self.location(span);
+        self.errors.push(format!("{}: {}", location, message));
+    }
+
+    /// Expands the references in `text`, the value of `item`, adding the values
+    /// substituted to `secrets`.
+    fn expand(&mut self, item: &Item, text: &str, secrets: &mut Vec<String>) -> Option<String> {
+        if self.raw {
+            return Some(text.to_string());
+        }
+        match interpolate::expand(text, secrets) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                self.error(item.span(), e);
+                None
+            }
+        }
+    }
+
+    /// Parses the `[[check]]` tables in `item`, starting from `template`.
+    fn checks(&mut self, item: &Item, template: &Metric, checks: &mut Vec<Metric>) {
+        let Some(tables) = item.as_array_of_tables() else {
+            self.error(item.span(), "check must be an array of tables".to_string());
+            return;
+        };
+        for table in tables.iter() {
+            if let Some(metrics) = self.check(table, template) {
+                checks.extend(metrics);
+            }
+        }
+    }
+
+    /// Parses a `[[check]]` table. With `hosts`, it's a template for one check per host.
+    fn check(&mut self, table: &Table, template: &Metric) -> Option<Vec<Metric>> {
+        let mut metric = Metric {
+            source: self.location(table.span()),
+            ..template.clone()
+        };
+        let mut hosts = None;
+        let mut valid = true;
+        // Without an interval in its group, a check needs its own
+        let required = ["name", "every", "worker"];
+        for key in required {
+            let inherited = key == "every" && template.n > 0;
+            if !table.contains_key(key) && !inherited {
+                self.error(table.span(), format!("Missing {} in check", key));
+                valid = false;
+            }
+        }
+        for (key, item) in table.iter() {
+            match key {
+                "name" | "worker" | "args" | "group" => {
+                    let Some(text) = item.as_str() else {
+                        self.error(item.span(), format!("{} must be a string", key));
+                        valid = false;
+                        continue;
+                    };
+                    match key {
+                        "name" => metric.short_name = text.to_string(),
+                        "worker" => metric.function = text.to_string(),
+                        "group" => metric.group = text.to_string(),
+                        _ => match self.expand(item, text, &mut metric.secrets) {
+                            Some(text) => metric.args = text,
+                            None => valid = false,
+                        },
+                    }
+                }
+                "every" => match scalar(item).map(|every| options::parse_schedule(&every)) {
+                    Some(Ok((n, cron))) => {
+                        metric.n = n;
+                        metric.cron = cron;
+                    }
+                    Some(Err(e)) => {
+                        self.error(item.span(), e);
+                        valid = false;
+                    }
+                    None => {
+                        self.error(item.span(), "Invalid every".to_string());
+                        valid = false;
+                    }
+                },
+                "hosts" => match self.strings(item) {
+                    Some(list) => hosts = Some(list),
+                    None => valid = false,
+                },
+                _ => valid &= self.option(&mut metric, key, item),
+            }
+        }
+        if !valid {
+            return None;
+        }
+        match hosts {
+            Some(hosts) => Some(
+                hosts
+                    .iter()
+                    .map(|host| options::for_host(&metric, host))
+                    .collect(),
+            ),
+            None => Some(vec![metric]),
+        }
+    }
+
+    /// Applies a check option. Returns whether it was valid.
+    fn option(&mut self, metric: &mut Metric, key: &str, item: &Item) -> bool {
+        let value = match item.as_array() {
+            Some(_) => self.strings(item).map(|list| list.join(",")),
+            None => scalar(item),
+        };
+        let Some(value) = value else {
+            self.error(item.span(), format!("Invalid value for {}", key));
+            return false;
+        };
+        let Some(value) = self.expand(item, &value, &mut metric.secrets) else {
+            return false;
+        };
+        match options::apply(metric, key, &value) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error(item.span(), e);
+                false
+            }
+        }
+    }
+
+    fn setting(&mut self, settings: &mut Settings, key: &str, item: &Item) {
+        let Some(text) = scalar(item) else {
+            self.error(item.span(), format!("Invalid value for {}", key));
+            return;
+        };
+        let result = match key {
+            "max_concurrency" => match text.parse::<usize>() {
+                Ok(max) if max > 0 => {
+                    settings.max_concurrency = Some(max);
+                    Ok(())
+                }
+                _ => Err(format!("Invalid max_concurrency '{}'", text)),
+            },
+            "splay" => text.parse().map(|splay| {
+                settings.splay = Some(splay);
+            }),
+            "splay_max" => options::parse_seconds(&text).map(|max| {
+                settings.splay_max = Some(max);
+            }),
+            "shutdown_timeout" => options::parse_seconds(&text).map(|timeout| {
+                settings.shutdown_timeout = Some(timeout);
+            }),
+            _ => options::parse_positive_seconds(&text).map(|every| {
+                settings.watch_config = Some(every);
+            }),
+        };
+        if let Err(e) = result {
+            self.error(item.span(), e);
+        }
+    }
+
+    fn strings(&mut self, item: &Item) -> Option<Vec<String>> {
+        let list = item.as_array().and_then(|array| {
+            array
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+        });
+        if list.is_none() {
+            self.error(item.span(), "Expected a list of strings".to_string());
+        }
+        list
+    }
+}
+
+/// A string, number or boolean as text, the way options are written in jr.conf.
+fn scalar(item: &Item) -> Option<String> {
+    match item.as_value()? {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        Value::Boolean(b) => Some(b.value().to_string()),
+        _ => None,
+    }
+}
+
+/// Writes a configuration in the TOML format. Options are written on each check
+/// that differs from the defaults, since groups don't keep their own settings.
+pub fn to_toml(config: &Config) -> String {
+    let mut doc = DocumentMut::new();
+    let settings = &config.settings;
+    if let Some(max) = settings.max_concurrency {
+        doc["max_concurrency"] = value(max as i64);
+    }
+    if let Some(splay) = settings.splay {
+        doc["splay"] = value(splay.to_string());
+    }
+    if let Some(max) = settings.splay_max {
+        doc["splay_max"] = seconds(max);
+    }
+    if let Some(timeout) = settings.shutdown_timeout {
+        doc["shutdown_timeout"] = seconds(timeout);
+    }
+    if let Some(every) = settings.watch_config {
+        doc["watch_config"] = seconds(every);
+    }
+    // The global outputs are also the ones checks inherit
+    let defaults = Metric::default();
+    let inherited = settings.outputs.as_ref().unwrap_or(&defaults.outputs);
+    if let Some(outputs) = &settings.outputs {
+        doc["outputs"] = value(outputs_array(outputs));
+    }
+
+    let mut checks = ArrayOfTables::new();
+    let mut groups = Table::new();
+    groups.set_implicit(true);
+    for metric in &config.checks {
+        let table = check_table(metric, inherited);
+        if metric.group == "Default" {
+            checks.push(table);
+        } else {
+            let group = groups.entry(&metric.group).or_insert_with(|| {
+                let mut group = Table::new();
+                group.set_implicit(true);
+                Item::Table(group)
+            });
+            let group = group.as_table_mut().expect("groups are tables");
+            match group
+                .get_mut("check")
+                .and_then(Item::as_array_of_tables_mut)
+            {
+                Some(checks) => checks.push(table),
+                None => {
+                    let mut checks = ArrayOfTables::new();
+                    checks.push(table);
+                    group.insert("check", Item::ArrayOfTables(checks));
+                }
+            }
+        }
+    }
+    if !checks.is_empty() {
+        doc.insert("check", Item::ArrayOfTables(checks));
+    }
+    if !groups.is_empty() {
+        doc.insert("group", Item::Table(groups));
+    }
+    doc.to_string()
+}
+
+/// The table of a check, whose outputs are written when they aren't `inherited`.
+fn check_table(metric: &Metric, inherited: &[String]) -> Table {
+    let defaults = Metric::default();
+    let mut table = Table::new();
+    table["name"] = value(&metric.short_name);
+    table["every"] = match &metric.cron {
+        Some(cron) => value(cron.to_string()),
+        None => value(metric.n as i64),
+    };
+    table["worker"] = value(&metric.function);
+    table["args"] = value(&metric.args);
+    if metric.outputs != inherited {
+        table["outputs"] = value(outputs_array(&metric.outputs));
+    }
+    if !metric.tags.is_empty() {
+        table["tags"] = value(metric.tags.iter().collect::<Array>());
+    }
+    if metric.reporter != defaults.reporter {
+        table["reporter"] = value(&metric.reporter);
+    }
+    if metric.catch_up != CatchUp::default() {
+        table["catch_up"] = value(metric.catch_up.to_string());
+    }
+    if !metric.jitter.is_zero() {
+        table["jitter"] = seconds(metric.jitter);
+    }
+    if let Some(timeout) = metric.timeout {
+        table["timeout"] = seconds(timeout);
+    }
+    if metric.timezone != Tz::UTC {
+        table["tz"] = value(metric.timezone.name());
+    }
+    if let Some(active) = &metric.active {
+        table["active"] = value(active.to_string());
+    }
+    if let Some(every) = metric.inactive_every {
+        table["inactive"] = value(every as i64);
+    }
+    if metric.retries > 0 {
+        table["retries"] = value(metric.retries as i64);
+    }
+    if let Some(interval) = metric.retry_interval {
+        table["retry_interval"] = seconds(interval);
+    }
+    if let Some(min) = metric.min_value {
+        table["min_value"] = value(min);
+    }
+    if let Some(max) = metric.max_value {
+        table["max_value"] = value(max);
+    }
+    if let Some(min) = metric.warn_min_value {
+        table["warn_min_value"] = value(min);
+    }
+    if let Some(max) = metric.warn_max_value {
+        table["warn_max_value"] = value(max);
+    }
+    table
+}
+
+/// A duration as a number of seconds, whole if it can be.
+fn seconds(duration: Duration) -> Item {
+    if duration.subsec_nanos() == 0 {
+        value(duration.as_secs() as i64)
+    } else {
+        value(duration.as_secs_f64())
+    }
+}
+
+/// A list of outputs, with `none` for no output at all.
+fn outputs_array(outputs: &[String]) -> Array {
+    if outputs.is_empty() {
+        ["none"].into_iter().collect()
+    } else {
+        outputs.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Splay;
+
+    const EXAMPLE: &str = r#"
+max_concurrency = 8
+splay = "hash"
+outputs = ["stdout", "angelweb"]
+timeout = 20
+
+[[check]]
+name = "load_avg"
+every = 30
+worker = "load_avg"
+args = "mordor"
+
+[[check]]
+name = "nightly"
+every = "15 0 * * *"
+worker = "timethis"
+args = "sleep 3"
+outputs = ["-angelweb"]
+
+[group.office]
+tz = "Europe/Madrid"
+active = "Mon-Fri 09:00-18:00"
+retries = 2
+
+[[group.office.check]]
+name = "web"
+every = 60
+worker = "check_url"
+args = "https://example.com/a::b"
+timeout = 5.5
+min_value = 1
+"#;
+
+    #[test]
+    fn test_parse_toml() {
+        let mut settings = Settings::default();
+        let mut errors = Vec::new();
+        let template = Metric {
+            group: "Default".to_string(),
+            ..Default::default()
+        };
+        let document = parse(
+            EXAMPLE,
+            Path::new("jr.toml"),
+            template,
+            &mut settings,
+            &mut errors,
+            false,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(settings.max_concurrency, Some(8));
+        assert_eq!(settings.splay, Some(Splay::Hash));
+
+        let checks = document.checks;
+        assert_eq!(checks.len(), 3);
+        assert_eq!(checks[0].short_name, "load_avg");
+        assert_eq!(checks[0].source, "jr.toml:7");
+        assert_eq!(checks[0].group, "Default");
+        assert_eq!(checks[0].outputs, vec!["stdout", "angelweb"]);
+        assert_eq!(checks[0].timeout, Some(Duration::from_secs(20)));
+        assert!(checks[1].cron.is_some());
+        assert_eq!(checks[1].outputs, vec!["stdout"]);
+
+        assert_eq!(checks[2].group, "office");
+        assert_eq!(checks[2].args, "https://example.com/a::b");
+        assert_eq!(checks[2].timezone, "Europe/Madrid".parse::<Tz>().unwrap());
+        assert!(checks[2].active.is_some());
+        assert_eq!(checks[2].retries, 2);
+        assert_eq!(checks[2].timeout, Some(Duration::from_millis(5500)));
+        assert_eq!(checks[2].min_value, Some(1.0));
+    }
+
+    #[test]
+    fn test_parse_toml_errors() {
+        let content = r#"
+[[check]]
+name = "web"
+every = 60
+worker = "check_url"
+colour = "blue"
+
+[[check]]
+name = "disk"
+worker = "df"
+"#;
+        let mut errors = Vec::new();
+        let document = parse(
+            content,
+            Path::new("jr.toml"),
+            Metric::default(),
+            &mut Settings::default(),
+            &mut errors,
+            false,
+        );
+        assert!(document.checks.is_empty());
+        assert_eq!(
+            errors,
+            vec![
+                "jr.toml:6: Unknown option 'colour'",
+                "jr.toml:8: Missing every in check",
+            ]
+        );
+
+        let mut errors = Vec::new();
+        parse(
+            "max_concurrency = \n",
+            Path::new("jr.toml"),
+            Metric::default(),
+            &mut Settings::default(),
+            &mut errors,
+            false,
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("jr.toml:1: "));
+    }
+
+    #[test]
+    fn test_parse_toml_hosts_and_groups() {
+        let mut errors = Vec::new();
+        let content = r#"
+[[check]]
+name = "web_up"
+every = 60
+worker = "check_url"
+args = "https://{host}/health"
+hosts = ["web1.example.com", "web2.example.com"]
+
+[group.Mordor]
+every = 30
+tags = ["team=ops"]
+
+[[group.Mordor.check]]
+name = "load"
+worker = "load_avg"
+"#;
+        let document = parse(
+            content,
+            Path::new("jr.toml"),
+            Metric::default(),
+            &mut Settings::default(),
+            &mut errors,
+            false,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let checks = document.checks;
+        assert_eq!(checks.len(), 3);
+        assert_eq!(checks[0].short_name, "web_up_web1_example_com");
+        assert_eq!(checks[1].args, "https://web2.example.com/health");
+        assert_eq!(checks[1].source, "jr.toml:2");
+        assert_eq!(checks[2].n, 30);
+        assert_eq!(checks[2].tags, vec!["team=ops"]);
+    }
+
+    #[test]
+    fn test_to_toml_round_trip() {
+        let mut errors = Vec::new();
+        let mut settings = Settings::default();
+        let template = Metric {
+            group: "Default".to_string(),
+            ..Default::default()
+        };
+        let document = parse(
+            EXAMPLE,
+            Path::new("jr.toml"),
+            template.clone(),
+            &mut settings,
+            &mut errors,
+            false,
+        );
+        let config = Config {
+            checks: document.checks,
+            settings,
+            ..Default::default()
+        };
+
+        let converted = to_toml(&config);
+        let mut settings = Settings::default();
+        let document = parse(
+            &converted,
+            Path::new("converted.toml"),
+            template,
+            &mut settings,
+            &mut errors,
+            false,
+        );
+        assert!(errors.is_empty(), "{:?}\n{}", errors, converted);
+        assert_eq!(settings.max_concurrency, Some(8));
+        assert_eq!(document.checks.len(), config.checks.len());
+        for (converted, original) in document.checks.iter().zip(&config.checks) {
+            assert!(converted.same_config(original), "{}", converted.short_name);
+        }
+        assert!(converted.contains("[[group.office.check]]"));
+    }
+}
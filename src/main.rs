@@ -1,75 +1,161 @@
-use std::collections::HashMap;
 use std::process::exit;
-use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
 
+mod commands;
 mod config;
 use config::cmdline;
 use config::file as conf;
 
+mod nagios;
+use nagios::State;
+
 mod output;
-use output::angelweb;
-use output::graphite;
-use output::stdout as out;
+use output::Outputs;
+
+mod scheduler;
+use scheduler::Scheduler;
+
+mod signals;
 
 mod worker;
-use worker::check_url;
-use worker::df;
-use worker::load_avg;
-use worker::query_api;
-use worker::runthis;
-use worker::timethis;
+use worker::{Registry, Worker};
 
 mod types;
 use crate::types::Metric;
 
 fn main() {
-    let mut function_map: HashMap<String, fn(Metric) -> Metric> = HashMap::new();
+    let mut args = cmdline::parse_args();
+    let command = args.command.take();
+    let cmdline_config = cmdline::config_from_args(args);
+    if let Some(command) = command {
+        exit(commands::run(command, &cmdline_config));
+    }
 
-    let mut configs = conf::parse_config();
-    configs.extend_from_slice(&cmdline::parse_config());
-    if configs.is_empty() {
+    let mut config = conf::load_config(&cmdline_config);
+    if config.checks.is_empty() {
         eprintln!("No configuration found. Please provide command-line arguments or a configuration file. Use `jr --help` for more information.");
         exit(1);
     }
 
-    let now = Instant::now();
-
-    // This will be completely dynamic, plug-in based
-    function_map.insert("check_url".to_string(), check_url::run);
-    function_map.insert("load_avg".to_string(), load_avg::run);
-    function_map.insert("timethis".to_string(), timethis::run);
-    function_map.insert("runthis".to_string(), runthis::run);
-    function_map.insert("df".to_string(), df::run);
-    function_map.insert("query_api".to_string(), query_api::run);
-
-    loop {
-        let start_time = now.elapsed().as_millis();
-        let iteration = now.elapsed().as_secs(); // increments per second
-
-        for metric in &mut configs {
-            if let Some(func) = function_map.get(&metric.function) {
-                // Only run every metric.n seconds
-                if iteration % metric.n == 0 {
-                    let result_metric = func(metric.clone());
-                    *metric = result_metric;
-                    out::run(metric);
-                    let _ = graphite::run(metric);
-                    let _ = angelweb::run(metric);
-                }
-            }
+    let mut errors = Vec::new();
+    let checks = create_workers(config.checks.drain(..).collect(), &mut errors);
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+
+    // With --nagios, stdout is kept for the plugin output
+    let once = checks.iter().any(|(metric, _)| metric.once);
+    let nagios = config.settings.nagios;
+    let quiet = match nagios.then(nagios::StdoutToStderr::new) {
+        Some(Ok(redirect)) => Some(redirect),
+        Some(Err(e)) => {
+            eprintln!("Failed to redirect the standard output: {}", e);
+            None
+        }
+        None => None,
+    };
+
+    let mut outputs = Outputs::new(output::Registry::with_builtins());
+    for (metric, _) in &checks {
+        outputs.open(&metric.outputs);
+    }
+    let mut scheduler = Scheduler::new(checks, &config.settings);
+
+    let controller = scheduler.controller();
+    let stopper = controller.clone();
+    if let Err(e) = signals::on_stop(move || stopper.stop()) {
+        eprintln!("Failed to handle signals: {}", e);
+    }
+
+    // Reloads keep the checks given on the command line, and the old configuration
+    // if the new one has any error
+    let settings = config.settings.clone();
+    let watched = Arc::new(Mutex::new(config.files.clone()));
+    let files = Arc::clone(&watched);
+    let reload = Arc::new(move || {
+        let config = conf::load_config(&cmdline_config);
+        let mut errors = Vec::new();
+        let checks = create_workers(config.checks, &mut errors);
+        for error in &errors {
+            eprintln!("{}", error);
         }
+        if !config.errors.is_empty() || !errors.is_empty() || checks.is_empty() {
+            eprintln!("Keeping the previous configuration");
+            return;
+        }
+        let changed = settings.needing_restart(&config.settings);
+        if !changed.is_empty() {
+            eprintln!(
+                "Changes to {} only take effect after a restart",
+                changed.join(", ")
+            );
+        }
+        controller.reload(checks);
+        // Files included since are watched too, and removed ones aren't anymore
+        if let Ok(mut files) = files.lock() {
+            *files = config.files;
+        }
+    });
+    let on_hup = Arc::clone(&reload);
+    if let Err(e) = signals::on_reload(move || on_hup()) {
+        eprintln!("Failed to handle signals: {}", e);
+    }
+    if let Some(every) = config.settings.watch_config {
+        conf::watch(watched, every, move || reload());
+    }
+
+    scheduler.run(&mut outputs);
+    scheduler.teardown();
+    for error in outputs.flush() {
+        eprintln!("{}", error);
+    }
 
-        // Wait for the next interval
-        println!("Run took {}us", now.elapsed().as_millis() - start_time);
-        let elapsed_nanos = now.elapsed().as_nanos();
-        sleep(Duration::new(
-            0,
-            1_000_000_000 - (elapsed_nanos % 1_000_000_000) as u32,
-        ));
+    if once {
+        // Checks that couldn't be set up count as UNKNOWN
+        let results = scheduler.results();
+        let mut state = nagios::worst(results.iter().copied());
+        if !errors.is_empty() {
+            state = state.max(State::Unknown);
+        }
+        drop(quiet);
+        if nagios {
+            println!("{}", nagios::plugin_output(&results, state));
+        }
+        exit(state.code());
+    }
+}
 
-        if configs.iter().any(|c| c.once) {
-            break;
+/// Creates and sets up the worker of each check. Checks that can't run are left
+/// out, with a message in `errors`.
+fn create_workers(
+    metrics: Vec<Metric>,
+    errors: &mut Vec<String>,
+) -> Vec<(Metric, Box<dyn Worker>)> {
+    let registry = Registry::with_builtins();
+    let mut checks: Vec<(Metric, Box<dyn Worker>)> = Vec::new();
+    for metric in metrics {
+        let Some(mut worker) = registry.create(&metric.function) else {
+            errors.push(format!(
+                "Unknown worker '{}' for check '{}'. Skipping it.",
+                metric.function, metric.short_name
+            ));
+            continue;
+        };
+        if let Err(e) = worker.validate_args(&metric.args) {
+            errors.push(format!(
+                "Invalid arguments for check '{}': {}. Skipping it.",
+                metric.short_name, e
+            ));
+            continue;
+        }
+        if let Err(e) = worker.setup(&metric) {
+            errors.push(format!(
+                "Failed to set up check '{}': {}. Skipping it.",
+                metric.short_name, e
+            ));
+            continue;
         }
+        checks.push((metric, worker));
     }
+    checks
 }
@@ -0,0 +1,233 @@
+// Results of `--once` in the terms of Nagios plugins: the exit code, and with
+// `--nagios` the single line of output with perfdata.
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::types::{Metric, Status};
+
+/// The state of a check, ordered from the best to the worst. UNKNOWN is better
+/// than WARNING, as with check_multi: a check that couldn't tell is less alarming
+/// than one that found a problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Ok,
+    Unknown,
+    Warning,
+    Critical,
+}
+
+impl State {
+    pub fn of(metric: &Metric) -> State {
+        match metric.status {
+            Status::Ok => State::Ok,
+            Status::Warning => State::Warning,
+            Status::Critical | Status::Timeout => State::Critical,
+            Status::Unknown => State::Unknown,
+        }
+    }
+
+    /// The exit code of a plugin in this state.
+    pub fn code(self) -> i32 {
+        match self {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Critical => 2,
+            State::Unknown => 3,
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Ok => write!(f, "OK"),
+            State::Warning => write!(f, "WARNING"),
+            State::Critical => write!(f, "CRITICAL"),
+            State::Unknown => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+/// The worst state of `metrics`, OK if there's none.
+pub fn worst<'a>(metrics: impl IntoIterator<Item = &'a Metric>) -> State {
+    metrics
+        .into_iter()
+        .map(State::of)
+        .max()
+        .unwrap_or(State::Ok)
+}
+
+/// The plugin output for `metrics`: `JR <STATE> - <summary> | <perfdata>`. The
+/// summary lists the checks that aren't ok, or the result of the only check.
+pub fn plugin_output(metrics: &[&Metric], state: State) -> String {
+    let problems: Vec<String> = metrics
+        .iter()
+        .filter(|metric| State::of(metric) != State::Ok)
+        .map(|metric| {
+            format!(
+                "{} {}: {}",
+                metric.short_name,
+                State::of(metric),
+                text(metric)
+            )
+        })
+        .collect();
+    let summary = match (metrics, problems.is_empty()) {
+        ([metric], _) => format!("{}: {}", metric.short_name, text(metric)),
+        (_, true) => format!("{} check(s) ok", metrics.len()),
+        (_, false) => format!(
+            "{}; {} of {} check(s) not ok",
+            problems.join(", "),
+            problems.len(),
+            metrics.len()
+        ),
+    };
+    let perfdata: Vec<String> = metrics.iter().filter_map(|m| perfdata(m)).collect();
+    let mut line = format!("JR {} - {}", state, summary.replace('|', "/"));
+    if !perfdata.is_empty() {
+        line.push_str(" | ");
+        line.push_str(&perfdata.join(" "));
+    }
+    line
+}
+
+/// What the check found: its message, or else its value.
+fn text(metric: &Metric) -> String {
+    match (&metric.message, metric.value) {
+        (Some(message), _) => metric.redact(message.lines().next().unwrap_or_default()),
+        (None, Some(value)) => format!("{}{}", value, metric.units.as_deref().unwrap_or("")),
+        (None, None) => "no result".to_string(),
+    }
+}
+
+/// The units of measure of perfdata.
+const UNITS: &[&str] = &["s", "ms", "us", "%", "B", "KB", "MB", "GB", "TB", "c"];
+
+/// `'label'=value[UOM];warn;crit;;`, with the warning and critical thresholds as
+/// Nagios ranges. Units Nagios doesn't know are left out.
+fn perfdata(metric: &Metric) -> Option<String> {
+    let value = metric.value?;
+    let uom = metric
+        .units
+        .as_deref()
+        .filter(|units| UNITS.contains(units))
+        .unwrap_or_default();
+    let label = &metric.short_name;
+    let label = if label.contains([' ', '\'', '=']) {
+        format!("'{}'", label.replace('\'', "''"))
+    } else {
+        label.to_string()
+    };
+    Some(format!(
+        "{}={}{};{};{};;",
+        label,
+        value,
+        uom,
+        range(metric.warn_min_value, metric.warn_max_value),
+        range(metric.min_value, metric.max_value)
+    ))
+}
+
+/// The Nagios range alerting outside of `min`..`max`: `min:max`, `min:` or `~:max`.
+fn range(min: Option<f64>, max: Option<f64>) -> String {
+    match (min, max) {
+        (None, None) => String::new(),
+        (Some(min), None) => format!("{}:", min),
+        (None, Some(max)) => format!("~:{}", max),
+        (Some(min), Some(max)) => format!("{}:{}", min, max),
+    }
+}
+
+/// Sends what's written to the standard output to the standard error instead,
+/// until dropped, so that the plugin output is alone on stdout. Commands run by
+/// the checks inherit it.
+pub struct StdoutToStderr {
+    saved: libc::c_int,
+}
+
+impl StdoutToStderr {
+    pub fn new() -> io::Result<Self> {
+        io::stdout().flush()?;
+        // SAFETY: dup() and dup2() only work on file descriptors, which we own here.
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+            let error = io::Error::last_os_error();
+            unsafe { libc::close(saved) };
+            return Err(error);
+        }
+        Ok(StdoutToStderr { saved })
+    }
+}
+
+impl Drop for StdoutToStderr {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        // SAFETY: `saved` is the original stdout, duplicated in new().
+        unsafe {
+            libc::dup2(self.saved, libc::STDOUT_FILENO);
+            libc::close(self.saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(name: &str, status: Status, value: Option<f64>, message: Option<&str>) -> Metric {
+        Metric {
+            short_name: name.to_string(),
+            status,
+            value,
+            message: message.map(|m| m.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_worst() {
+        let ok = metric("a", Status::Ok, Some(1.0), None);
+        let timeout = metric("b", Status::Timeout, None, Some("Timed out"));
+        let odd = metric("c", Status::Unknown, None, None);
+        assert_eq!(worst([&ok]), State::Ok);
+        assert_eq!(worst([&ok, &odd]), State::Unknown);
+        assert_eq!(worst([&ok, &odd, &timeout]), State::Critical);
+        assert_eq!(worst([]), State::Ok);
+        assert_eq!(State::Unknown.code(), 3);
+    }
+
+    #[test]
+    fn test_plugin_output() {
+        let load = Metric {
+            units: Some("%".to_string()),
+            warn_max_value: Some(80.0),
+            min_value: Some(0.0),
+            max_value: Some(95.0),
+            ..metric("cpu load", Status::Ok, Some(42.5), None)
+        };
+        let web = Metric {
+            units: Some("ms".to_string()),
+            ..metric(
+                "web",
+                Status::Critical,
+                Some(120.0),
+                Some("HTTP error: 500 | oops"),
+            )
+        };
+        let up = metric("up", Status::Ok, Some(1.0), None);
+
+        assert_eq!(
+            plugin_output(&[&load, &up], worst([&load, &up])),
+            "JR OK - 2 check(s) ok | 'cpu load'=42.5%;~:80;0:95;; up=1;;;;"
+        );
+        assert_eq!(
+            plugin_output(&[&load, &web, &up], worst([&load, &web, &up])),
+            "JR CRITICAL - web CRITICAL: HTTP error: 500 / oops; 1 of 3 check(s) not ok \
+             | 'cpu load'=42.5%;~:80;0:95;; web=120ms;;;; up=1;;;;"
+        );
+        assert_eq!(plugin_output(&[&up], State::Ok), "JR OK - up: 1 | up=1;;;;");
+    }
+}
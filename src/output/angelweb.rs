@@ -1,10 +1,31 @@
 // An output plugin to push the measure to angelweb.
+use crate::output::Output;
 use crate::types::Metric;
-use serde_json::json;
+use serde_json::{json, Value};
 use std::env;
+use std::error::Error;
 use std::fs;
 
-pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
+pub struct Angelweb;
+
+impl Output for Angelweb {
+    fn name(&self) -> &'static str {
+        "angelweb"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        run(metric)
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        match payload(metric) {
+            Some(payload) => serde_json::to_string_pretty(&payload).ok(),
+            None => Some("nothing, the check has no graph_short_name".to_string()),
+        }
+    }
+}
+
+pub fn run(metric: &Metric) -> Result<(), Box<dyn Error>> {
     // If JR_TEST_OUTPUT_FILE is set, write the JSON payload to the specified file
     if let Ok(output_file) = env::var("JR_TEST_OUTPUT_FILE") {
         let payload = json!({
@@ -12,13 +33,16 @@ pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
             "graph_value": metric.graph_value.unwrap_or(0),
             "units": metric.units.as_deref().unwrap_or(""),
             "group": metric.group,
-            "reporter": "jr@mordor",
+            "reporter": metric.reporter,
+            "tags": metric.tags,
             "type": metric.graph_type.as_deref().unwrap_or("g"),
             "graph_type": metric.graph_type.as_deref().unwrap_or(""),
             "min_value": metric.min_value,
             "max_value": metric.max_value,
             "every": if metric.once { -1 } else { metric.n as i64 },
-            "status": metric.status
+            "status": metric.status.legacy_str(),
+            "attempt": metric.attempt,
+            "state_type": state_type(metric)
         });
         fs::write(output_file, serde_json::to_string_pretty(&payload)?)?;
         return Ok(());
@@ -27,38 +51,19 @@ pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
     let angelweb_server =
         env::var("ANGELWEB_SERVER").unwrap_or_else(|_| "http://127.0.0.1:4000".to_string());
 
-    // graph_value and graph_short_name
-    let units = metric.units.as_deref().unwrap_or("");
-    let group = &metric.group;
-    let value: i64 = metric.graph_value.unwrap_or(0);
-    let short_name = match metric.graph_short_name.as_deref() {
-        Some(name) => name,
-        None => {
-            eprintln!(
-                "Warning: metric '{}' is missing 'graph_short_name'. Skipping angelweb output.",
-                metric.short_name
-            );
-            return Ok(());
-        }
+    let Some(payload) = payload(metric) else {
+        eprintln!(
+            "Warning: metric '{}' is missing 'graph_short_name'. Skipping angelweb output.",
+            metric.short_name
+        );
+        return Ok(());
     };
-    let graph_type = metric.graph_type.as_deref().unwrap_or("");
 
-    println!("Angelweb is at {}. Sending {:?}", angelweb_server, value);
-
-    // Make the HTTP request
-    let client = reqwest::blocking::Client::new();
-    let payload = json!({
-        "short_name": short_name,
-        "graph_value": value,
-        "units": units,
-        "group": group,
-        "reporter": "jr@mordor",
-        "graph_type": graph_type,
-        "min_value": metric.min_value,
-        "max_value": metric.max_value,
-        "every": if metric.once { -1 } else { metric.n as i64 },
-        "status": &metric.status
-    });
+    println!(
+        "Angelweb is at {}. Sending {:?}",
+        angelweb_server,
+        metric.graph_value.unwrap_or(0)
+    );
 
     if env::var("DEBUG").unwrap_or_else(|_| "0".to_string()) == "1" {
         println!(
@@ -67,6 +72,8 @@ pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
         );
     }
 
+    // Make the HTTP request
+    let client = reqwest::blocking::Client::new();
     let res = client
         .post(format!("{}//api/v1/metric", angelweb_server))
         .json(&payload)
@@ -75,8 +82,42 @@ pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
     if res.status().is_success() {
         Ok(())
     } else {
-        println!("Something else happened. Status: {:?}", res.status());
-        Ok(())
+        Err(format!("angelweb answered with status {}", res.status()).into())
+    }
+}
+
+/// The JSON sent for `metric`, if it has the graph_short_name angelweb needs.
+fn payload(metric: &Metric) -> Option<Value> {
+    // graph_value and graph_short_name
+    let units = metric.units.as_deref().unwrap_or("");
+    let group = &metric.group;
+    let value: i64 = metric.graph_value.unwrap_or(0);
+    let short_name = metric.graph_short_name.as_deref()?;
+    let graph_type = metric.graph_type.as_deref().unwrap_or("");
+
+    Some(json!({
+        "short_name": short_name,
+        "graph_value": value,
+        "units": units,
+        "group": group,
+        "reporter": &metric.reporter,
+        "tags": &metric.tags,
+        "graph_type": graph_type,
+        "min_value": metric.min_value,
+        "max_value": metric.max_value,
+        "every": if metric.once { -1 } else { metric.n as i64 },
+        "status": metric.status.legacy_str(),
+        "attempt": metric.attempt,
+        "state_type": state_type(metric)
+    }))
+}
+
+/// Whether the status is final ("hard") or a failed attempt still being retried ("soft").
+fn state_type(metric: &Metric) -> &'static str {
+    if metric.soft {
+        "soft"
+    } else {
+        "hard"
     }
 }
 
@@ -105,16 +146,27 @@ mod tests {
         let content = fs::read_to_string(output_file_path).unwrap();
         let json: Value = serde_json::from_str(&content).unwrap();
         assert_eq!(json["every"], 60);
+        assert_eq!(json["reporter"], "jr@mordor");
+        assert_eq!(json["attempt"], 0);
+        assert_eq!(json["state_type"], "hard");
 
-        // Test with 'once' set to true
+        // Test with 'once' set to true, on a failed attempt to be retried
         let metric2 = Metric {
             once: true,
+            attempt: 1,
+            soft: true,
+            reporter: "jr@gondor".to_string(),
+            tags: vec!["team=web".to_string()],
             ..Default::default()
         };
         run(&metric2).unwrap();
         let content = fs::read_to_string(output_file_path).unwrap();
         let json: Value = serde_json::from_str(&content).unwrap();
         assert_eq!(json["every"], -1);
+        assert_eq!(json["reporter"], "jr@gondor");
+        assert_eq!(json["tags"], serde_json::json!(["team=web"]));
+        assert_eq!(json["attempt"], 1);
+        assert_eq!(json["state_type"], "soft");
 
         // Clean up
         env::remove_var("JR_TEST_OUTPUT_FILE");
@@ -0,0 +1,276 @@
+// An output plugin to send the values to a Graphite carbon daemon over TCP, with
+// the plaintext protocol (`path value timestamp` lines) or the pickle one.
+//
+// It's configured from the environment:
+//
+//     CARBON_SERVER    host or host:port, 127.0.0.1 by default
+//     CARBON_PROTOCOL  plaintext (port 2003 by default) or pickle (port 2004)
+//     CARBON_PREFIX    the first component of the paths, jr by default
+use std::env;
+use std::error::Error;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::output::{with_default_port, Output};
+use crate::types::Metric;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Plaintext,
+    Pickle,
+}
+
+impl Protocol {
+    fn default_port(self) -> u16 {
+        match self {
+            Protocol::Plaintext => 2003,
+            Protocol::Pickle => 2004,
+        }
+    }
+}
+
+pub struct Carbon {
+    /// host:port
+    server: String,
+    protocol: Protocol,
+    prefix: String,
+    /// Kept open between results, and opened again when a write fails.
+    stream: Option<TcpStream>,
+}
+
+impl Carbon {
+    pub fn from_env() -> Self {
+        let protocol = match env::var("CARBON_PROTOCOL").as_deref() {
+            Ok("pickle") => Protocol::Pickle,
+            _ => Protocol::Plaintext,
+        };
+        let server = env::var("CARBON_SERVER").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let prefix = env::var("CARBON_PREFIX").unwrap_or_else(|_| "jr".to_string());
+        Carbon::new(&server, protocol, &prefix)
+    }
+
+    pub fn new(server: &str, protocol: Protocol, prefix: &str) -> Self {
+        let server = with_default_port(server, protocol.default_port());
+        Carbon {
+            server,
+            protocol,
+            prefix: prefix.to_string(),
+            stream: None,
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, Box<dyn Error>> {
+        let mut last_error = None;
+        for address in self.server.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(format!("can't connect to carbon at {}: {}", self.server, e).into()),
+            None => Err(format!("no address for carbon at {}", self.server).into()),
+        }
+    }
+
+    /// Writes `data`, on a new connection if the previous one was closed.
+    fn write(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if let Some(stream) = &mut self.stream {
+            if stream.write_all(data).is_ok() {
+                return Ok(());
+            }
+            self.stream = None;
+        }
+        let mut stream = self.connect()?;
+        stream.write_all(data)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// `prefix.group.name`, with the characters carbon doesn't like replaced.
+    fn path(&self, metric: &Metric) -> String {
+        [&self.prefix, &metric.group, &metric.short_name]
+            .iter()
+            .filter(|component| !component.is_empty())
+            .map(|component| sanitize(component))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+impl Output for Carbon {
+    fn name(&self) -> &'static str {
+        "carbon"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        let Some(value) = metric.value else {
+            return Ok(()); // Nothing to graph
+        };
+        let path = self.path(metric);
+        let timestamp = now();
+        let data = match self.protocol {
+            Protocol::Plaintext => plaintext(&path, value, timestamp).into_bytes(),
+            Protocol::Pickle => pickle(&[(path, value, timestamp)]),
+        };
+        self.write(&data)
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        let line = match metric.value {
+            Some(value) => plaintext(&self.path(metric), value, now()),
+            None => "nothing, the check has no value".to_string(),
+        };
+        match self.protocol {
+            Protocol::Plaintext => Some(line),
+            Protocol::Pickle => Some(format!("{} (pickled)", line.trim_end())),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A path component: dots, spaces and other special characters become `_`.
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_:@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// A line of the plaintext protocol.
+pub fn plaintext(path: &str, value: f64, timestamp: u64) -> String {
+    format!("{} {} {}\n", path, value, timestamp)
+}
+
+/// A message of the pickle protocol: the length of the payload as a 4-byte big
+/// endian number, then a pickled list of `(path, (timestamp, value))`.
+pub fn pickle(points: &[(String, f64, u64)]) -> Vec<u8> {
+    let mut payload = vec![0x80, 2]; // PROTO 2
+    payload.extend_from_slice(b"](");
+    for (path, value, timestamp) in points {
+        payload.push(b'X'); // BINUNICODE
+        payload.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        payload.extend_from_slice(path.as_bytes());
+        payload.push(b'G'); // BINFLOAT
+        payload.extend_from_slice(&(*timestamp as f64).to_be_bytes());
+        payload.push(b'G');
+        payload.extend_from_slice(&value.to_be_bytes());
+        payload.extend_from_slice(&[0x86, 0x86]); // TUPLE2, twice
+    }
+    payload.extend_from_slice(b"e."); // APPENDS, STOP
+    let mut message = (payload.len() as u32).to_be_bytes().to_vec();
+    message.extend(payload);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_path_and_plaintext() {
+        let carbon = Carbon::new("localhost", Protocol::Plaintext, "jr");
+        assert_eq!(carbon.server, "localhost:2003");
+        let metric = Metric {
+            group: "Mordor".to_string(),
+            short_name: "disk /var.log".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(carbon.path(&metric), "jr.Mordor.disk__var_log");
+        assert_eq!(
+            plaintext("jr.load", 0.5, 1700000000),
+            "jr.load 0.5 1700000000\n"
+        );
+
+        assert_eq!(
+            Carbon::new("::1", Protocol::Pickle, "").server,
+            "[::1]:2004"
+        );
+        assert_eq!(
+            Carbon::new("[::1]", Protocol::Pickle, "").server,
+            "[::1]:2004"
+        );
+        assert_eq!(
+            Carbon::new("[::1]:2103", Protocol::Pickle, "").server,
+            "[::1]:2103"
+        );
+    }
+
+    #[test]
+    fn test_pickle() {
+        let message = pickle(&[("jr.a".to_string(), 1.5, 2)]);
+        let mut expected = vec![0, 0, 0, 35, 0x80, 2, b']', b'(', b'X', 4, 0, 0, 0];
+        expected.extend_from_slice(b"jr.a");
+        expected.push(b'G');
+        expected.extend_from_slice(&2f64.to_be_bytes());
+        expected.push(b'G');
+        expected.extend_from_slice(&1.5f64.to_be_bytes());
+        expected.extend_from_slice(&[0x86, 0x86, b'e', b'.']);
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_send_plaintext_and_pickle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let metric = Metric {
+            short_name: "load".to_string(),
+            value: Some(0.25),
+            ..Default::default()
+        };
+
+        let mut carbon = Carbon::new(&server, Protocol::Plaintext, "jr");
+        carbon.send(&metric).unwrap();
+        carbon.send(&Metric::default()).unwrap(); // No value, nothing sent
+        carbon.send(&metric).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        for _ in 0..2 {
+            let line = lines.next().unwrap().unwrap();
+            assert!(line.starts_with("jr.load 0.25 "), "{}", line);
+        }
+
+        let mut carbon = Carbon::new(&server, Protocol::Pickle, "jr");
+        carbon.send(&metric).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut length = [0; 4];
+        stream.read_exact(&mut length).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        assert!(payload.ends_with(&[0x86, 0x86, b'e', b'.']));
+    }
+
+    #[test]
+    fn test_send_without_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mut carbon = Carbon::new(&server, Protocol::Plaintext, "jr");
+        let metric = Metric {
+            value: Some(1.0),
+            ..Default::default()
+        };
+        assert!(carbon.send(&metric).is_err());
+        assert!(carbon.stream.is_none());
+    }
+}
@@ -1,8 +1,26 @@
+use crate::output::Output;
 use crate::types::Metric;
 use std::env;
+use std::error::Error;
 use std::net::UdpSocket;
 
-pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
+pub struct Graphite;
+
+impl Output for Graphite {
+    fn name(&self) -> &'static str {
+        "graphite"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        run(metric)
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        Some(format(metric))
+    }
+}
+
+pub fn run(metric: &Metric) -> Result<(), Box<dyn Error>> {
     let ip_address = match env::var("GRAPHITE_SERVER") {
         Ok(value) => value,
         Err(_) => "127.0.0.1".to_string(),
@@ -11,6 +29,19 @@ pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
     // Create a UDP socket bound to a random local port
     let socket = UdpSocket::bind("0.0.0.0:0")?;
 
+    let formatted_data = format(metric);
+    println!("{}", formatted_data);
+
+    let data = formatted_data.into_bytes();
+
+    // Send the data to the specified address on port 8125
+    socket.send_to(&data, format!("{}:8125", ip_address))?;
+
+    Ok(())
+}
+
+/// The line sent for `metric`.
+pub fn format(metric: &Metric) -> String {
     let graph_short_name = metric
         .graph_short_name
         .as_deref()
@@ -27,15 +58,12 @@ pub fn run(metric: &Metric) -> Result<(), Box<dyn std::error::Error>> {
 
     // Format the string according to the specified pattern
     let every = if metric.once { -1 } else { metric.n as i64 };
-    let formatted_data = format!(
+    format!(
         "jr.{};every={}.{}:{}|{}",
-        graph_short_name, every, metric.status, value, metric_type
-    );
-    println!("{}", formatted_data);
-    let data = formatted_data.into_bytes();
-
-    // Send the data to the specified address on port 8125
-    socket.send_to(&data, format!("{}:8125", ip_address))?;
-
-    Ok(())
+        graph_short_name,
+        every,
+        metric.status.legacy_str(),
+        value,
+        metric_type
+    )
 }
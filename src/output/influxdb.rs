@@ -0,0 +1,457 @@
+// An output plugin that writes the results as InfluxDB line-protocol points, to
+// the HTTP API of InfluxDB 2 or to a UDP listener. Points are batched and written
+// every flush interval, when the batch is full, and when jr exits:
+//
+//     load,function=load_avg,group=Mordor,status=ok value=0.5,graph_value=1i 1700000000000000000
+//
+// It's configured from the environment:
+//
+//     INFLUXDB_URL             http://host:8086 (http://127.0.0.1:8086 by default),
+//                              or udp://host:port (port 8089 by default)
+//     INFLUXDB_TOKEN           sent as `Authorization: Token <token>` over HTTP
+//     INFLUXDB_ORG             the organization written to over HTTP
+//     INFLUXDB_BUCKET          the bucket written to over HTTP, jr by default
+//     INFLUXDB_FLUSH_INTERVAL  seconds between writes, 10 by default
+//     INFLUXDB_BATCH_SIZE      the most points kept before writing, 5000 by default
+use std::env;
+use std::error::Error;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::blocking::Client;
+use reqwest::Url;
+
+use crate::output::{with_default_port, Output};
+use crate::types::Metric;
+
+const DEFAULT_URL: &str = "http://127.0.0.1:8086";
+const DEFAULT_UDP_PORT: u16 = 8089;
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_BATCH_SIZE: usize = 5000;
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The largest UDP packet, so that points aren't fragmented.
+const UDP_PAYLOAD: usize = 1400;
+
+pub enum Transport {
+    /// `/api/v2/write` of the server at this URL.
+    Http {
+        url: String,
+        token: Option<String>,
+        org: Option<String>,
+        bucket: String,
+    },
+    /// host:port
+    Udp { server: String },
+}
+
+pub struct Influxdb {
+    transport: Transport,
+    flush_interval: Duration,
+    batch_size: usize,
+    /// The points not written yet.
+    pending: Vec<String>,
+    last_write: Instant,
+    client: Client,
+    /// The socket and the address of the UDP listener.
+    socket: Option<(UdpSocket, SocketAddr)>,
+}
+
+impl Influxdb {
+    pub fn from_env() -> Self {
+        let url = env::var("INFLUXDB_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+        let transport = match url.strip_prefix("udp://") {
+            Some(server) => Transport::Udp {
+                server: with_default_port(server.trim_end_matches('/'), DEFAULT_UDP_PORT),
+            },
+            None => Transport::Http {
+                url: url.trim_end_matches('/').to_string(),
+                token: env::var("INFLUXDB_TOKEN").ok(),
+                org: env::var("INFLUXDB_ORG").ok(),
+                bucket: env::var("INFLUXDB_BUCKET").unwrap_or_else(|_| "jr".to_string()),
+            },
+        };
+        let flush_interval = env::var("INFLUXDB_FLUSH_INTERVAL")
+            .ok()
+            .and_then(|seconds| seconds.parse().ok())
+            .map_or(DEFAULT_FLUSH_INTERVAL, Duration::from_secs);
+        let batch_size = env::var("INFLUXDB_BATCH_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_BATCH_SIZE);
+        Influxdb::new(transport, flush_interval, batch_size)
+    }
+
+    pub fn new(transport: Transport, flush_interval: Duration, batch_size: usize) -> Self {
+        Influxdb {
+            transport,
+            flush_interval,
+            batch_size: batch_size.max(1),
+            pending: Vec::new(),
+            last_write: Instant::now(),
+            client: Client::builder()
+                .timeout(TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            socket: None,
+        }
+    }
+
+    /// `<url>/api/v2/write?org=<org>&bucket=<bucket>&precision=ns`
+    fn write_url(&self) -> Result<Url, Box<dyn Error>> {
+        let Transport::Http {
+            url, org, bucket, ..
+        } = &self.transport
+        else {
+            return Err("not an HTTP InfluxDB".into());
+        };
+        let mut write_url = Url::parse(url)?;
+        write_url
+            .path_segments_mut()
+            .map_err(|_| format!("invalid InfluxDB URL {}", url))?
+            .pop_if_empty()
+            .extend(["api", "v2", "write"]);
+        {
+            let mut query = write_url.query_pairs_mut();
+            if let Some(org) = org {
+                query.append_pair("org", org);
+            }
+            query
+                .append_pair("bucket", bucket)
+                .append_pair("precision", "ns");
+        }
+        Ok(write_url)
+    }
+
+    fn write_http(&self, points: &[String]) -> Result<(), Box<dyn Error>> {
+        let mut request = self
+            .client
+            .post(self.write_url()?)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(points.join("\n"));
+        if let Transport::Http {
+            token: Some(token), ..
+        } = &self.transport
+        {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        let res = request.send()?;
+        if !res.status().is_success() {
+            return Err(format!("InfluxDB answered with status {}", res.status()).into());
+        }
+        Ok(())
+    }
+
+    /// Sends the points in as few packets as fit them. The socket is kept only if
+    /// it worked, so that the server is resolved again after a failure.
+    fn write_udp(&mut self, server: &str, points: &[String]) -> Result<(), Box<dyn Error>> {
+        let (socket, address) = match self.socket.take() {
+            Some(socket) => socket,
+            None => connect(server)?,
+        };
+        for packet in packets(points, UDP_PAYLOAD) {
+            socket.send_to(packet.as_bytes(), address)?;
+        }
+        self.socket = Some((socket, address));
+        Ok(())
+    }
+}
+
+impl Output for Influxdb {
+    fn name(&self) -> &'static str {
+        "influxdb"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        let Some(point) = point(metric, now()) else {
+            return Ok(()); // Nothing to write
+        };
+        self.pending.push(point);
+        if self.pending.len() >= self.batch_size {
+            return self.flush();
+        }
+        Ok(())
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        let point =
+            point(metric, now()).unwrap_or_else(|| "nothing, the check has no fields".to_string());
+        match &self.transport {
+            Transport::Http { .. } => Some(format!("POST {}\n{}", self.write_url().ok()?, point)),
+            Transport::Udp { server } => Some(format!("to udp://{}\n{}", server, point)),
+        }
+    }
+
+    /// Writes the pending points. Points that can't be written are dropped.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.last_write = Instant::now();
+        let pending = std::mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        match &self.transport {
+            Transport::Http { .. } => self.write_http(&pending),
+            Transport::Udp { server } => {
+                let server = server.clone();
+                self.write_udp(&server, &pending)
+            }
+        }
+    }
+
+    /// Writes the pending points once the flush interval has passed.
+    fn idle(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.last_write.elapsed() < self.flush_interval {
+            return Ok(());
+        }
+        self.flush()
+    }
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Opens a socket of the family of the first address `server` resolves to.
+fn connect(server: &str) -> Result<(UdpSocket, SocketAddr), Box<dyn Error>> {
+    let address = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("no address for InfluxDB at {}", server))?;
+    let local = match address {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    Ok((UdpSocket::bind(local)?, address))
+}
+
+/// The points joined by newlines into packets of up to `size` bytes. A point
+/// longer than that gets a packet of its own.
+fn packets(points: &[String], size: usize) -> Vec<String> {
+    let mut packets: Vec<String> = Vec::new();
+    for point in points {
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + point.len() <= size => {
+                packet.push('\n');
+                packet.push_str(point);
+            }
+            _ => packets.push(point.clone()),
+        }
+    }
+    packets
+}
+
+/// The line-protocol point of `metric` at `timestamp` (in nanoseconds): the name
+/// of the check as the measurement, its group, function and status as tags, and
+/// its value, graph value and message as fields. None without any field.
+pub fn point(metric: &Metric, timestamp: u128) -> Option<String> {
+    let mut fields = Vec::new();
+    if let Some(value) = metric.value.filter(|value| value.is_finite()) {
+        fields.push(format!("value={}", float(value)));
+    }
+    if let Some(graph_value) = metric.graph_value {
+        fields.push(format!("graph_value={}i", graph_value));
+    }
+    if let Some(message) = &metric.message {
+        let message = metric
+            .redact(message)
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        fields.push(format!("message=\"{}\"", message.replace('\n', "\\n")));
+    }
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = escape(&metric.short_name, ", ");
+    for (key, value) in [
+        ("function", metric.function.as_str()),
+        ("group", metric.group.as_str()),
+        ("status", metric.status.as_str()),
+    ] {
+        // Tags with an empty value aren't allowed
+        if !value.is_empty() {
+            line.push_str(&format!(",{}={}", key, escape(value, ",= ")));
+        }
+    }
+    Some(format!("{} {} {}", line, fields.join(","), timestamp))
+}
+
+/// `text` with `special` characters escaped, and line breaks, which can't be, as
+/// spaces.
+fn escape(text: &str, special: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' | '\r' => escaped.push_str("\\ "),
+            c if special.contains(c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A float field, which needs a decimal point not to be taken for an integer by
+/// InfluxDB 1.
+fn float(value: f64) -> String {
+    let text = value.to_string();
+    match text.contains(['.', 'e']) {
+        true => text,
+        false => format!("{}.0", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Status;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    fn load() -> Metric {
+        Metric {
+            short_name: "load avg".to_string(),
+            group: "Mordor".to_string(),
+            function: "load_avg".to_string(),
+            value: Some(0.5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_point() {
+        assert_eq!(
+            point(&load(), 42).unwrap(),
+            "load\\ avg,function=load_avg,group=Mordor,status=ok value=0.5 42"
+        );
+        let gate = Metric {
+            short_name: "load avg,5m".to_string(),
+            group: "Black Gate, East".to_string(),
+            ..load()
+        };
+        assert_eq!(
+            point(&gate, 42).unwrap(),
+            "load\\ avg\\,5m,function=load_avg,group=Black\\ Gate\\,\\ East,status=ok value=0.5 42"
+        );
+        let web = Metric {
+            short_name: "web,front".to_string(),
+            group: "Gondor West".to_string(),
+            function: "check_url".to_string(),
+            status: Status::Critical,
+            value: Some(2.0),
+            graph_value: Some(1),
+            message: Some("HTTP error: \"500\" for secret".to_string()),
+            secrets: vec!["secret".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            point(&web, 42).unwrap(),
+            "web\\,front,function=check_url,group=Gondor\\ West,status=critical \
+             value=2.0,graph_value=1i,message=\"HTTP error: \\\"500\\\" for ***\" 42"
+        );
+        assert_eq!(point(&Metric::default(), 42), None);
+    }
+
+    #[test]
+    fn test_packets() {
+        let points = ["a".repeat(5), "b".repeat(4), "c".repeat(12)].map(String::from);
+        assert_eq!(
+            packets(&points, 10),
+            vec!["aaaaa\nbbbb".to_string(), "c".repeat(12)]
+        );
+    }
+
+    fn http(url: &str, flush_interval: Duration, batch_size: usize) -> Influxdb {
+        let transport = Transport::Http {
+            url: url.to_string(),
+            token: Some("s3cr3t".to_string()),
+            org: Some("Middle Earth".to_string()),
+            bucket: "jr".to_string(),
+        };
+        Influxdb::new(transport, flush_interval, batch_size)
+    }
+
+    #[test]
+    fn test_write_http() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/influx/api/v2/write"),
+                request::query(url_decoded(contains(("org", "Middle Earth")))),
+                request::query(url_decoded(contains(("bucket", "jr")))),
+                request::query(url_decoded(contains(("precision", "ns")))),
+                request::headers(contains(("authorization", "Token s3cr3t"))),
+                request::body(matches("^load\\\\ avg,.* value=0.5 \\d+\nload")),
+            ])
+            .respond_with(status_code(204)),
+        );
+
+        let mut influxdb = http(&server.url_str("/influx/"), Duration::from_secs(3600), 10);
+        influxdb.send(&load()).unwrap();
+        influxdb.send(&load()).unwrap();
+        influxdb.idle().unwrap(); // Not time yet
+        assert_eq!(influxdb.pending.len(), 2);
+        influxdb.flush().unwrap();
+        influxdb.flush().unwrap(); // Nothing pending anymore
+    }
+
+    #[test]
+    fn test_batching() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("POST", "/api/v2/write"))
+                .times(2)
+                .respond_with(status_code(204)),
+        );
+
+        // Written when the batch is full
+        let mut influxdb = http(&server.url_str(""), Duration::from_secs(3600), 2);
+        influxdb.send(&load()).unwrap();
+        influxdb.send(&load()).unwrap();
+        assert!(influxdb.pending.is_empty());
+
+        // And when the interval has passed
+        let mut influxdb = http(&server.url_str(""), Duration::ZERO, 10);
+        influxdb.send(&load()).unwrap();
+        influxdb.idle().unwrap();
+        assert!(influxdb.pending.is_empty());
+    }
+
+    #[test]
+    fn test_write_error() {
+        let server = Server::run();
+        server
+            .expect(Expectation::matching(request::method("POST")).respond_with(status_code(401)));
+        let mut influxdb = http(&server.url_str(""), Duration::ZERO, 10);
+        influxdb.send(&load()).unwrap();
+        assert_eq!(
+            influxdb.flush().unwrap_err().to_string(),
+            "InfluxDB answered with status 401 Unauthorized"
+        );
+        assert!(influxdb.pending.is_empty());
+    }
+
+    #[test]
+    fn test_write_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = Transport::Udp {
+            server: server.local_addr().unwrap().to_string(),
+        };
+        let mut influxdb = Influxdb::new(transport, DEFAULT_FLUSH_INTERVAL, 10);
+        influxdb.send(&load()).unwrap();
+        influxdb.send(&load()).unwrap();
+        influxdb.flush().unwrap();
+
+        let mut packet = [0; 2048];
+        let (len, _) = server.recv_from(&mut packet).unwrap();
+        let packet = String::from_utf8_lossy(&packet[..len]).to_string();
+        let lines: Vec<&str> = packet.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].starts_with("load\\ avg,function=load_avg,group=Mordor,status=ok value=0.5 ")
+        );
+    }
+}
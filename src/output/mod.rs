@@ -1,3 +1,236 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::types::Metric;
+
 pub mod angelweb;
+pub mod carbon;
 pub mod graphite;
+pub mod influxdb;
+pub mod prometheus;
+pub mod pushgateway;
+pub mod statsd;
 pub mod stdout;
+
+/// The outputs a check sends its results to unless the configuration says otherwise.
+pub const DEFAULT_OUTPUTS: &[&str] = &["stdout", "graphite", "angelweb"];
+
+/// Who results are reported as unless the configuration says otherwise.
+pub const DEFAULT_REPORTER: &str = "jr@mordor";
+
+/// An output plugin. A single instance is shared by all the checks routed to it.
+pub trait Output: Send {
+    /// The name used in `Outputs` directives and `outputs=` check options.
+    fn name(&self) -> &'static str;
+
+    /// Sends the result of a check.
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>>;
+
+    /// What `send` would send for `metric`, shown by `jr run`. `None` if the output
+    /// can't tell.
+    fn render(&self, _metric: &Metric) -> Option<String> {
+        None
+    }
+
+    /// Sends whatever the output still holds. Called when jr exits.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called whenever the scheduler is about to wait. Flushes by default; outputs
+    /// sending batches on their own schedule can wait for it.
+    fn idle(&mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()
+    }
+}
+
+/// `server` as `host:port`, with `port` if it has none. IPv6 addresses can be
+/// given bare or in brackets.
+pub fn with_default_port(server: &str, port: u16) -> String {
+    if server.starts_with('[') {
+        match server.contains("]:") {
+            true => server.to_string(),
+            false => format!("{}:{}", server, port),
+        }
+    } else {
+        match server.matches(':').count() {
+            0 => format!("{}:{}", server, port),
+            1 => server.to_string(),
+            _ => format!("[{}]:{}", server, port),
+        }
+    }
+}
+
+pub type OutputFactory = fn() -> Box<dyn Output>;
+
+/// Maps output names to factories.
+#[derive(Default)]
+pub struct Registry {
+    factories: BTreeMap<&'static str, OutputFactory>,
+}
+
+impl Registry {
+    /// A registry with all the outputs shipped with jr.
+    pub fn with_builtins() -> Self {
+        let mut registry = Registry::default();
+        registry.register(|| Box::new(angelweb::Angelweb));
+        registry.register(|| Box::new(carbon::Carbon::from_env()));
+        registry.register(|| Box::new(graphite::Graphite));
+        registry.register(|| Box::new(influxdb::Influxdb::from_env()));
+        registry.register(|| Box::new(prometheus::Prometheus::from_env()));
+        registry.register(|| Box::new(pushgateway::Pushgateway::from_env()));
+        registry.register(|| Box::new(statsd::Statsd::from_env()));
+        registry.register(|| Box::new(stdout::Stdout));
+        registry
+    }
+
+    /// Registers an output under the name it reports.
+    pub fn register(&mut self, factory: OutputFactory) {
+        let name = factory().name();
+        self.factories.insert(name, factory);
+    }
+
+    pub fn create(&self, name: &str) -> Option<Box<dyn Output>> {
+        self.factories.get(name).map(|factory| factory())
+    }
+}
+
+/// Routes results to the outputs each check is configured with. Outputs are
+/// created the first time a check is routed to them.
+pub struct Outputs {
+    registry: Registry,
+    instances: BTreeMap<String, Box<dyn Output>>,
+}
+
+impl Outputs {
+    pub fn new(registry: Registry) -> Self {
+        Outputs {
+            registry,
+            instances: BTreeMap::new(),
+        }
+    }
+
+    /// Creates the outputs in `names` ahead of their first result, for the ones that
+    /// serve results to start with jr. Unknown ones are reported when sending.
+    pub fn open(&mut self, names: &[String]) {
+        for name in names {
+            self.instance(name);
+        }
+    }
+
+    /// Sends `metric` to each of its outputs. Returns one message per failed output.
+    pub fn send(&mut self, metric: &Metric) -> Vec<String> {
+        let mut errors = Vec::new();
+        for name in &metric.outputs {
+            let output = match self.instance(name) {
+                Some(output) => output,
+                None => {
+                    errors.push(format!("Unknown output '{}'", name));
+                    continue;
+                }
+            };
+            if let Err(e) = output.send(metric) {
+                errors.push(format!("Output '{}' failed: {}", name, e));
+            }
+        }
+        errors
+    }
+
+    /// Flushes every output created so far. Returns one message per failed output.
+    pub fn flush(&mut self) -> Vec<String> {
+        self.each(|output| output.flush())
+    }
+
+    /// Tells every output created so far that jr is about to wait. Returns one
+    /// message per failed output.
+    pub fn idle(&mut self) -> Vec<String> {
+        self.each(|output| output.idle())
+    }
+
+    fn each(
+        &mut self,
+        mut call: impl FnMut(&mut Box<dyn Output>) -> Result<(), Box<dyn Error>>,
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (name, output) in &mut self.instances {
+            if let Err(e) = call(output) {
+                errors.push(format!("Output '{}' failed: {}", name, e));
+            }
+        }
+        errors
+    }
+
+    fn instance(&mut self, name: &str) -> Option<&mut Box<dyn Output>> {
+        if !self.instances.contains_key(name) {
+            let output = self.registry.create(name)?;
+            self.instances.insert(name.to_string(), output);
+        }
+        self.instances.get_mut(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SENT: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counting;
+
+    impl Output for Counting {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn send(&mut self, _metric: &Metric) -> Result<(), Box<dyn Error>> {
+            SENT.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Output for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn send(&mut self, _metric: &Metric) -> Result<(), Box<dyn Error>> {
+            Err("connection refused".into())
+        }
+    }
+
+    #[test]
+    fn test_outputs_routing_and_errors() {
+        let mut registry = Registry::default();
+        registry.register(|| Box::new(Counting));
+        registry.register(|| Box::new(Failing));
+        let mut outputs = Outputs::new(registry);
+
+        let metric = Metric {
+            outputs: vec![
+                "counting".to_string(),
+                "failing".to_string(),
+                "nonexistent".to_string(),
+            ],
+            ..Default::default()
+        };
+        let errors = outputs.send(&metric);
+        assert_eq!(SENT.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            errors,
+            vec![
+                "Output 'failing' failed: connection refused".to_string(),
+                "Unknown output 'nonexistent'".to_string(),
+            ]
+        );
+
+        let metric = Metric {
+            outputs: Vec::new(),
+            ..Default::default()
+        };
+        assert!(outputs.send(&metric).is_empty());
+        assert_eq!(SENT.load(Ordering::SeqCst), 1);
+    }
+}
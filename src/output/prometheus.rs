@@ -0,0 +1,363 @@
+// An output plugin that keeps the last result of each check and serves them on
+// `/metrics`, for Prometheus to scrape, in its text format or in the OpenMetrics
+// one when asked for. The HTTP server starts when jr first waits for checks, on
+// $PROMETHEUS_LISTEN (0.0.0.0:9184 by default).
+//
+// Each check is a gauge named after it, and every check also has a
+// `jr_check_up` series (1 when its last result is ok) and the age of its last
+// result:
+//
+//     # TYPE jr_load_avg gauge
+//     jr_load_avg{check="load_avg",group="Mordor",function="load_avg",status="ok",units=""} 0.52
+//     # TYPE jr_check_up gauge
+//     jr_check_up{check="load_avg",group="Mordor",function="load_avg"} 1
+//     # TYPE jr_check_age_seconds gauge
+//     jr_check_age_seconds{check="load_avg",group="Mordor",function="load_avg"} 12.5
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::output::Output;
+use crate::types::{Metric, Status};
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:9184";
+const OPENMETRICS: &str = "application/openmetrics-text";
+
+/// The last result of each check, by name, and when it was received.
+type Results = Arc<Mutex<BTreeMap<String, (Metric, Instant)>>>;
+
+pub struct Prometheus {
+    listen: String,
+    results: Results,
+    /// Where the server listens, once started.
+    address: Option<SocketAddr>,
+    /// Whether starting the server was tried. It isn't tried again, so that a
+    /// failure is only reported once.
+    started: bool,
+}
+
+impl Prometheus {
+    pub fn from_env() -> Self {
+        let listen = env::var("PROMETHEUS_LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.to_string());
+        Prometheus::new(&listen)
+    }
+
+    pub fn new(listen: &str) -> Self {
+        Prometheus {
+            listen: listen.to_string(),
+            results: Arc::default(),
+            address: None,
+            started: false,
+        }
+    }
+
+    /// Starts serving `/metrics` from a background thread.
+    fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(&self.listen)
+            .map_err(|e| format!("can't listen on {}: {}", self.listen, e))?;
+        self.address = Some(listener.local_addr()?);
+        let results = Arc::clone(&self.results);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = serve(stream, &results) {
+                    eprintln!("Prometheus: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Output for Prometheus {
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        self.results
+            .lock()
+            .unwrap()
+            .insert(metric.short_name.clone(), (metric.clone(), Instant::now()));
+        Ok(())
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        let results =
+            BTreeMap::from([(metric.short_name.clone(), (metric.clone(), Instant::now()))]);
+        Some(exposition(&results, Instant::now(), false))
+    }
+
+    /// Starts the server, so that `/metrics` answers before the first result.
+    fn idle(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.started {
+            self.started = true;
+            self.start()?;
+        }
+        Ok(())
+    }
+}
+
+/// Answers a single HTTP request.
+fn serve(stream: TcpStream, results: &Results) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut openmetrics = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("accept") && value.contains(OPENMETRICS) {
+                openmetrics = true;
+            }
+        }
+    }
+
+    let mut words = request_line.split_whitespace();
+    let (status, content_type, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = exposition(&results.lock().unwrap(), Instant::now(), openmetrics);
+            let content_type = match openmetrics {
+                true => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                false => "text/plain; version=0.0.4; charset=utf-8",
+            };
+            ("200 OK", content_type, body)
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "Try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", String::new()),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// The results in the Prometheus text format, or in the OpenMetrics one.
+pub fn exposition(
+    results: &BTreeMap<String, (Metric, Instant)>,
+    now: Instant,
+    openmetrics: bool,
+) -> String {
+    // Samples by metric family, as checks may end up with the same name
+    let mut gauges: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut up = Vec::new();
+    let mut age = Vec::new();
+    for (metric, received) in results.values() {
+        if let Some(value) = metric.value {
+            let labels = labels(&[
+                ("check", &metric.short_name),
+                ("group", &metric.group),
+                ("function", &metric.function),
+                ("status", metric.status.as_str()),
+                ("units", metric.units.as_deref().unwrap_or_default()),
+            ]);
+            gauges
+                .entry(metric_name(&metric.short_name))
+                .or_default()
+                .push(format!("{} {}", labels, number(value)));
+        }
+        let labels = labels(&[
+            ("check", &metric.short_name),
+            ("group", &metric.group),
+            ("function", &metric.function),
+        ]);
+        let is_up = if metric.status == Status::Ok { 1 } else { 0 };
+        up.push(format!("{} {}", labels, is_up));
+        let seconds = now.saturating_duration_since(*received).as_secs_f64();
+        age.push(format!("{} {}", labels, number(seconds)));
+    }
+
+    let mut text = String::new();
+    let mut family = |name: &str, help: &str, samples: &[String]| {
+        text.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} gauge\n",
+            name, help, name
+        ));
+        for sample in samples {
+            text.push_str(&format!("{}{}\n", name, sample));
+        }
+    };
+    for (name, samples) in &gauges {
+        family(name, "The last value of the check.", samples);
+    }
+    if !results.is_empty() {
+        family(
+            "jr_check_up",
+            "Whether the last result of the check is ok.",
+            &up,
+        );
+        family(
+            "jr_check_age_seconds",
+            "How long ago the last result of the check was received.",
+            &age,
+        );
+    }
+    if openmetrics {
+        text.push_str("# EOF\n");
+    }
+    text
+}
+
+/// `jr_<name>`, with the characters Prometheus doesn't allow replaced by `_`.
+pub fn metric_name(short_name: &str) -> String {
+    let name: String = short_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("jr_{}", name)
+}
+
+/// `{name="value",...}`, with the values escaped.
+pub fn labels(labels: &[(&str, &str)]) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// A sample value, with the spelling Prometheus uses for infinities.
+pub fn number(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "NaN".to_string(),
+        v if v == f64::INFINITY => "+Inf".to_string(),
+        v if v == f64::NEG_INFINITY => "-Inf".to_string(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn load() -> Metric {
+        Metric {
+            short_name: "load.avg".to_string(),
+            group: "Mordor".to_string(),
+            function: "load_avg".to_string(),
+            value: Some(0.5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exposition() {
+        let now = Instant::now();
+        let down = Metric {
+            short_name: "web".to_string(),
+            group: "Gondor \"West\"".to_string(),
+            function: "check_url".to_string(),
+            status: Status::Critical,
+            ..Default::default()
+        };
+        let results = BTreeMap::from([
+            ("load.avg".to_string(), (load(), now)),
+            ("web".to_string(), (down, now - Duration::from_secs(3))),
+        ]);
+
+        let text = exposition(&results, now, false);
+        assert_eq!(
+            text,
+            "# HELP jr_load_avg The last value of the check.\n\
+             # TYPE jr_load_avg gauge\n\
+             jr_load_avg{check=\"load.avg\",group=\"Mordor\",function=\"load_avg\",status=\"ok\",units=\"\"} 0.5\n\
+             # HELP jr_check_up Whether the last result of the check is ok.\n\
+             # TYPE jr_check_up gauge\n\
+             jr_check_up{check=\"load.avg\",group=\"Mordor\",function=\"load_avg\"} 1\n\
+             jr_check_up{check=\"web\",group=\"Gondor \\\"West\\\"\",function=\"check_url\"} 0\n\
+             # HELP jr_check_age_seconds How long ago the last result of the check was received.\n\
+             # TYPE jr_check_age_seconds gauge\n\
+             jr_check_age_seconds{check=\"load.avg\",group=\"Mordor\",function=\"load_avg\"} 0\n\
+             jr_check_age_seconds{check=\"web\",group=\"Gondor \\\"West\\\"\",function=\"check_url\"} 3\n"
+        );
+        assert!(exposition(&results, now, true).ends_with("# EOF\n"));
+
+        // Checks named alike are told apart by their name
+        let underscored = Metric {
+            short_name: "load_avg".to_string(),
+            ..load()
+        };
+        let results = BTreeMap::from([
+            ("load.avg".to_string(), (load(), now)),
+            ("load_avg".to_string(), (underscored, now)),
+        ]);
+        let text = exposition(&results, now, false);
+        assert_eq!(text.matches("# TYPE jr_load_avg gauge\n").count(), 1);
+        assert!(text.contains("jr_load_avg{check=\"load.avg\","));
+        assert!(text.contains("jr_load_avg{check=\"load_avg\","));
+        assert_eq!(exposition(&BTreeMap::new(), now, false), "");
+    }
+
+    fn get(address: SocketAddr, path: &str, accept: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nAccept: {}\r\n\r\n",
+            path, accept
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let mut prometheus = Prometheus::new("127.0.0.1:0");
+        prometheus.idle().unwrap();
+        let address = prometheus.address.unwrap();
+        let response = get(address, "/metrics", "text/plain");
+        assert!(response.ends_with("\r\n\r\n"));
+
+        prometheus.send(&load()).unwrap();
+        prometheus.send(&load()).unwrap();
+        prometheus.idle().unwrap();
+        assert_eq!(prometheus.address, Some(address));
+
+        let response = get(address, "/metrics", "text/plain");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains("\r\n\r\n# HELP jr_load_avg"));
+
+        let response = get(address, "/metrics", OPENMETRICS);
+        assert!(response.contains("Content-Type: application/openmetrics-text"));
+        assert!(response.ends_with("# EOF\n"));
+
+        assert!(get(address, "/", "*/*").starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[test]
+    fn test_listen_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut prometheus = Prometheus::new(&taken.local_addr().unwrap().to_string());
+        assert!(prometheus
+            .idle()
+            .unwrap_err()
+            .to_string()
+            .starts_with("can't listen on 127.0.0.1:"));
+        // Reported once
+        prometheus.idle().unwrap();
+        prometheus.send(&load()).unwrap();
+    }
+}
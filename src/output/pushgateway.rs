@@ -0,0 +1,424 @@
+// An output plugin that pushes the results to a Prometheus Pushgateway, for
+// runs too short to be scraped, like `jr --once` from cron. Results are grouped
+// with job = the group of the check and instance = the host of its reporter, and
+// pushed when the outputs are flushed.
+//
+// It's configured from the environment:
+//
+//     PUSHGATEWAY_URL              http://127.0.0.1:9091 by default
+//     PROMETHEUS_REMOTE_WRITE_URL  also send the results there with the
+//                                  remote-write protocol, e.g.
+//                                  http://prometheus:9090/api/v1/write
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::blocking::Client;
+use reqwest::Url;
+
+use crate::output::prometheus::{labels, metric_name, number};
+use crate::output::Output;
+use crate::types::{Metric, Status};
+
+const DEFAULT_URL: &str = "http://127.0.0.1:9091";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Pushgateway {
+    url: String,
+    remote_write_url: Option<String>,
+    /// The results not pushed yet.
+    pending: Vec<Metric>,
+    client: Client,
+}
+
+impl Pushgateway {
+    pub fn from_env() -> Self {
+        let url = env::var("PUSHGATEWAY_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+        let remote_write_url = env::var("PROMETHEUS_REMOTE_WRITE_URL").ok();
+        Pushgateway::new(&url, remote_write_url.as_deref())
+    }
+
+    pub fn new(url: &str, remote_write_url: Option<&str>) -> Self {
+        Pushgateway {
+            url: url.trim_end_matches('/').to_string(),
+            remote_write_url: remote_write_url.map(|url| url.to_string()),
+            pending: Vec::new(),
+            client: Client::builder()
+                .timeout(TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// `<url>/metrics/job/<job>/instance/<instance>`
+    fn group_url(&self, (job, instance): &(String, String)) -> Result<Url, Box<dyn Error>> {
+        let mut url = Url::parse(&self.url)?;
+        url.path_segments_mut()
+            .map_err(|_| format!("invalid Pushgateway URL {}", self.url))?
+            .extend(["metrics", "job", job, "instance", instance]);
+        Ok(url)
+    }
+
+    fn push(&self, group: &(String, String), metrics: &[&Metric]) -> Result<(), Box<dyn Error>> {
+        let url = self.group_url(group)?;
+        let res = self
+            .client
+            .post(url)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(text(metrics))
+            .send()?;
+        if !res.status().is_success() {
+            return Err(format!("Pushgateway answered with status {}", res.status()).into());
+        }
+        Ok(())
+    }
+
+    fn remote_write(&self, url: &str, metrics: &[&Metric]) -> Result<(), Box<dyn Error>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let body = snap::raw::Encoder::new().compress_vec(&write_request(metrics, timestamp))?;
+        let res = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/x-protobuf")
+            .header("Content-Encoding", "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(body)
+            .send()?;
+        if !res.status().is_success() {
+            return Err(format!("remote write answered with status {}", res.status()).into());
+        }
+        Ok(())
+    }
+}
+
+impl Output for Pushgateway {
+    fn name(&self) -> &'static str {
+        "pushgateway"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        self.pending.push(metric.clone());
+        Ok(())
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        let group = grouping(metric);
+        let url = self.group_url(&group).ok()?;
+        Some(format!("POST {}\n{}", url, text(&[metric])))
+    }
+
+    /// Pushes the last pending result of each check, one request per group, then
+    /// sends them with remote write. Results that can't be pushed are dropped.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let pending = std::mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        // A series can only be pushed once, so a later result of a check wins
+        let mut groups: BTreeMap<(String, String), BTreeMap<&str, &Metric>> = BTreeMap::new();
+        for metric in &pending {
+            groups
+                .entry(grouping(metric))
+                .or_default()
+                .insert(&metric.short_name, metric);
+        }
+        let mut errors = Vec::new();
+        let mut latest = Vec::new();
+        for (group, metrics) in &groups {
+            let metrics: Vec<&Metric> = metrics.values().copied().collect();
+            if let Err(e) = self.push(group, &metrics) {
+                errors.push(e.to_string());
+            }
+            latest.extend(metrics);
+        }
+        if let Some(url) = &self.remote_write_url {
+            if let Err(e) = self.remote_write(url, &latest) {
+                errors.push(e.to_string());
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join(", ").into()),
+        }
+    }
+}
+
+/// The job and the instance of the results of `metric`: its group, or `jr`,
+/// and the host of its reporter.
+fn grouping(metric: &Metric) -> (String, String) {
+    let job = match metric.group.as_str() {
+        "" => "jr".to_string(),
+        group => group.to_string(),
+    };
+    let instance = match metric.reporter.rsplit_once('@') {
+        Some((_, host)) => host,
+        None => &metric.reporter,
+    };
+    (job, instance.to_string())
+}
+
+/// The gauge of each check with a value, and whether it's up, in the Prometheus
+/// text format.
+fn text(metrics: &[&Metric]) -> String {
+    // Samples by metric family, as checks may end up with the same name
+    let mut gauges: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for metric in metrics {
+        if let Some(value) = metric.value {
+            let labels = labels(&[
+                ("check", &metric.short_name),
+                ("function", &metric.function),
+                ("status", metric.status.as_str()),
+                ("units", metric.units.as_deref().unwrap_or_default()),
+            ]);
+            gauges
+                .entry(metric_name(&metric.short_name))
+                .or_default()
+                .push(format!("{} {}", labels, number(value)));
+        }
+    }
+    let mut text = String::new();
+    for (name, samples) in &gauges {
+        text.push_str(&format!("# TYPE {} gauge\n", name));
+        for sample in samples {
+            text.push_str(&format!("{}{}\n", name, sample));
+        }
+    }
+    text.push_str("# TYPE jr_check_up gauge\n");
+    for metric in metrics {
+        let labels = labels(&[
+            ("check", &metric.short_name),
+            ("function", &metric.function),
+        ]);
+        text.push_str(&format!("jr_check_up{} {}\n", labels, up(metric)));
+    }
+    text
+}
+
+fn up(metric: &Metric) -> f64 {
+    if metric.status == Status::Ok {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// A remote-write `WriteRequest` protobuf, with the same series as `text` and
+/// the job and instance as labels, all at `timestamp` (in milliseconds).
+pub fn write_request(metrics: &[&Metric], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for metric in metrics {
+        let (job, instance) = grouping(metric);
+        let mut series = Vec::new();
+        if let Some(value) = metric.value {
+            let labels = vec![
+                ("__name__", metric_name(&metric.short_name)),
+                ("check", metric.short_name.clone()),
+                ("function", metric.function.clone()),
+                ("instance", instance.clone()),
+                ("job", job.clone()),
+                ("status", metric.status.to_string()),
+                ("units", metric.units.clone().unwrap_or_default()),
+            ];
+            series.push((labels, value));
+        }
+        let labels = vec![
+            ("__name__", "jr_check_up".to_string()),
+            ("check", metric.short_name.clone()),
+            ("function", metric.function.clone()),
+            ("instance", instance),
+            ("job", job),
+        ];
+        series.push((labels, up(metric)));
+
+        for (labels, value) in series {
+            let mut time_series = Vec::new();
+            // Empty labels are the same as missing ones for Prometheus
+            for (name, value) in labels.into_iter().filter(|(_, value)| !value.is_empty()) {
+                let mut label = Vec::new();
+                field_bytes(&mut label, 1, name.as_bytes());
+                field_bytes(&mut label, 2, value.as_bytes());
+                field_bytes(&mut time_series, 1, &label);
+            }
+            let mut sample = Vec::new();
+            varint(&mut sample, 1 << 3 | 1); // value, 64-bit
+            sample.extend_from_slice(&value.to_le_bytes());
+            varint(&mut sample, 2 << 3); // timestamp, varint
+            varint(&mut sample, timestamp as u64);
+            field_bytes(&mut time_series, 2, &sample);
+            field_bytes(&mut request, 1, &time_series);
+        }
+    }
+    request
+}
+
+fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// A length-delimited field: a string or an embedded message.
+fn field_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(buffer, field << 3 | 2);
+    varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{bytes, http, matchers::*, responders::*, Expectation, Server};
+
+    fn load() -> Metric {
+        Metric {
+            short_name: "load".to_string(),
+            group: "Mordor".to_string(),
+            function: "load_avg".to_string(),
+            reporter: "jr@barad-dur".to_string(),
+            value: Some(0.5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let web = Metric {
+            short_name: "web".to_string(),
+            function: "check_url".to_string(),
+            status: Status::Critical,
+            ..Default::default()
+        };
+        assert_eq!(
+            text(&[&load(), &web]),
+            "# TYPE jr_load gauge\n\
+             jr_load{check=\"load\",function=\"load_avg\",status=\"ok\",units=\"\"} 0.5\n\
+             # TYPE jr_check_up gauge\n\
+             jr_check_up{check=\"load\",function=\"load_avg\"} 1\n\
+             jr_check_up{check=\"web\",function=\"check_url\"} 0\n"
+        );
+
+        // Checks named alike share their metric family, told apart by their name
+        let dotted = Metric {
+            short_name: "load.avg".to_string(),
+            ..load()
+        };
+        let underscored = Metric {
+            short_name: "load_avg".to_string(),
+            value: Some(0.7),
+            ..load()
+        };
+        let pushed = text(&[&dotted, &underscored]);
+        assert_eq!(pushed.split("# TYPE jr_load_avg gauge\n").count(), 2);
+        assert!(pushed.contains("jr_load_avg{check=\"load.avg\","));
+        assert!(pushed.contains("jr_load_avg{check=\"load_avg\","));
+        assert_eq!(grouping(&web), ("jr".to_string(), "mordor".to_string()));
+    }
+
+    #[test]
+    fn test_push_by_group() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/metrics/job/Mordor/instance/barad-dur"),
+                request::body(matches("jr_load\\{.*\\} 0.5\n")),
+            ])
+            .respond_with(status_code(200)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path(
+                "POST",
+                "/metrics/job/Gondor%20West/instance/mordor",
+            ))
+            .respond_with(status_code(200)),
+        );
+
+        let mut pushgateway = Pushgateway::new(&server.url_str("/"), None);
+        let gondor = Metric {
+            group: "Gondor West".to_string(),
+            reporter: "mordor".to_string(),
+            ..load()
+        };
+        pushgateway.send(&load()).unwrap();
+        pushgateway.send(&gondor).unwrap();
+        // Only the last result of a check is pushed
+        pushgateway.send(&load()).unwrap();
+        pushgateway.flush().unwrap();
+        // Nothing pending anymore
+        pushgateway.flush().unwrap();
+    }
+
+    #[test]
+    fn test_push_error() {
+        let server = Server::run();
+        server
+            .expect(Expectation::matching(request::method("POST")).respond_with(status_code(400)));
+        let mut pushgateway = Pushgateway::new(&server.url_str(""), None);
+        pushgateway.send(&load()).unwrap();
+        assert_eq!(
+            pushgateway.flush().unwrap_err().to_string(),
+            "Pushgateway answered with status 400 Bad Request"
+        );
+        assert!(pushgateway.pending.is_empty());
+    }
+
+    #[test]
+    fn test_remote_write() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path(
+                "POST",
+                "/metrics/job/Mordor/instance/barad-dur",
+            ))
+            .respond_with(status_code(200)),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/api/v1/write"),
+                request::headers(contains(("content-encoding", "snappy"))),
+                has_series,
+            ])
+            .respond_with(status_code(204)),
+        );
+
+        let mut pushgateway =
+            Pushgateway::new(&server.url_str(""), Some(&server.url_str("/api/v1/write")));
+        pushgateway.send(&load()).unwrap();
+        pushgateway.flush().unwrap();
+    }
+
+    /// Whether the body is a snappy-compressed write request with the series of
+    /// load().
+    fn has_series(request: &http::Request<bytes::Bytes>) -> bool {
+        let Ok(request) = snap::raw::Decoder::new().decompress_vec(request.body()) else {
+            return false;
+        };
+        let has = |text: &[u8]| request.windows(text.len()).any(|w| w == text);
+        has(b"jr_load") && has(b"jr_check_up") && has(b"barad-dur") && has(b"check")
+    }
+
+    #[test]
+    fn test_write_request() {
+        let metric = Metric {
+            value: None,
+            ..load()
+        };
+        let request = write_request(&[&metric], 1);
+        // One series, jr_check_up, whose first label is its name
+        assert_eq!(request[0], 1 << 3 | 2);
+        assert_eq!(request[1] as usize, request.len() - 2);
+        assert_eq!(request[2], 1 << 3 | 2);
+        assert_eq!(&request[4..6], &[1 << 3 | 2, 8]);
+        assert_eq!(&request[6..14], b"__name__");
+        assert_eq!(request[request.len() - 1], 1);
+        assert_eq!(
+            &request[request.len() - 10..request.len() - 2],
+            &1f64.to_le_bytes()
+        );
+    }
+}
@@ -0,0 +1,293 @@
+// An output plugin to send the values to a StatsD server over UDP, with DogStatsD
+// tags. Lines are batched into packets of up to the MTU, sent when the next line
+// doesn't fit or when the outputs are flushed.
+//
+// It's configured from the environment:
+//
+//     STATSD_SERVER  host or host:port (IPv4 or IPv6), 127.0.0.1:8125 by default
+//     STATSD_PREFIX  the prefix of the names, jr by default
+//     STATSD_TAGS    off for servers that don't know DogStatsD tags
+//     STATSD_MTU     the largest packet, 1432 bytes by default
+//     STATSD_SAMPLE_RATE  the fraction of the results sent, 1 (all) by default
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::output::{with_default_port, Output};
+use crate::types::Metric;
+
+const DEFAULT_PORT: u16 = 8125;
+const DEFAULT_MTU: usize = 1432;
+
+pub struct Statsd {
+    /// host:port
+    server: String,
+    prefix: String,
+    tags: bool,
+    mtu: usize,
+    /// The fraction of the results sent, the others are dropped here.
+    sample_rate: f64,
+    /// The lines not sent yet, separated by newlines.
+    buffer: String,
+    /// The socket and the address of the server.
+    socket: Option<(UdpSocket, SocketAddr)>,
+}
+
+impl Statsd {
+    pub fn from_env() -> Self {
+        let server = env::var("STATSD_SERVER").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let prefix = env::var("STATSD_PREFIX").unwrap_or_else(|_| "jr".to_string());
+        let tags = env::var("STATSD_TAGS").map_or(true, |tags| tags != "off");
+        let mtu = env::var("STATSD_MTU")
+            .ok()
+            .and_then(|mtu| mtu.parse().ok())
+            .unwrap_or(DEFAULT_MTU);
+        let sample_rate = env::var("STATSD_SAMPLE_RATE")
+            .ok()
+            .and_then(|rate| rate.parse().ok())
+            .filter(|rate| *rate > 0.0 && *rate <= 1.0)
+            .unwrap_or(1.0);
+        Statsd {
+            sample_rate,
+            ..Statsd::new(&server, &prefix, tags, mtu)
+        }
+    }
+
+    pub fn new(server: &str, prefix: &str, tags: bool, mtu: usize) -> Self {
+        Statsd {
+            server: with_default_port(server, DEFAULT_PORT),
+            prefix: prefix.to_string(),
+            tags,
+            mtu,
+            sample_rate: 1.0,
+            buffer: String::new(),
+            socket: None,
+        }
+    }
+
+    /// The line sent for `metric`, None if it has no value.
+    pub fn line(&self, metric: &Metric) -> Option<String> {
+        let value = metric.value?;
+        let metric_type = match metric.graph_type.as_deref() {
+            Some("time") => "ms",
+            _ => "g",
+        };
+        let name = match self.prefix.as_str() {
+            "" => sanitize(&metric.short_name),
+            prefix => format!("{}.{}", prefix, sanitize(&metric.short_name)),
+        };
+        let mut line = format!("{}:{}|{}", name, value, metric_type);
+        if self.sample_rate < 1.0 {
+            line.push_str(&format!("|@{}", self.sample_rate));
+        }
+        if self.tags {
+            line.push_str("|#");
+            line.push_str(&tags(metric).join(","));
+        }
+        Some(line)
+    }
+
+    /// Opens a socket of the family of the first address `server` resolves to.
+    fn connect(&self) -> Result<(UdpSocket, SocketAddr), Box<dyn Error>> {
+        let address = self
+            .server
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("no address for statsd at {}", self.server))?;
+        let local = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        Ok((UdpSocket::bind(local)?, address))
+    }
+
+    /// Sends a packet. The socket is kept only if it worked, so that the server is
+    /// resolved again after a failure.
+    fn send_packet(&mut self, packet: &str) -> Result<(), Box<dyn Error>> {
+        let (socket, address) = match self.socket.take() {
+            Some(socket) => socket,
+            None => self.connect()?,
+        };
+        socket.send_to(packet.as_bytes(), address)?;
+        self.socket = Some((socket, address));
+        Ok(())
+    }
+}
+
+impl Output for Statsd {
+    fn name(&self) -> &'static str {
+        "statsd"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        let Some(line) = self.line(metric) else {
+            return Ok(()); // Nothing to graph
+        };
+        if self.sample_rate < 1.0 && random_fraction() >= self.sample_rate {
+            return Ok(()); // Not in the sample
+        }
+        let mut result = Ok(());
+        if !self.buffer.is_empty() && self.buffer.len() + 1 + line.len() > self.mtu {
+            result = self.flush();
+        }
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(&line);
+        result
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        Some(
+            self.line(metric)
+                .unwrap_or_else(|| "nothing, the check has no value".to_string()),
+        )
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let packet = std::mem::take(&mut self.buffer);
+        self.send_packet(&packet)
+    }
+}
+
+/// The group, status and interval of the check, then its own tags, as DogStatsD
+/// `name:value` tags.
+fn tags(metric: &Metric) -> Vec<String> {
+    let every = if metric.once { -1 } else { metric.n as i64 };
+    let mut tags = Vec::new();
+    if !metric.group.is_empty() {
+        tags.push(format!("group:{}", sanitize(&metric.group)));
+    }
+    tags.push(format!("status:{}", metric.status));
+    tags.push(format!("every:{}", every));
+    for tag in &metric.tags {
+        match tag.split_once('=') {
+            Some((name, value)) => tags.push(format!("{}:{}", sanitize(name), sanitize(value))),
+            None => tags.push(sanitize(tag)),
+        }
+    }
+    tags
+}
+
+/// A number in [0, 1), from the random keys the standard library gives hash maps.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A name or tag: the characters of the StatsD syntax and whitespace become `_`.
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_whitespace() || ":|@#,".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Status;
+
+    fn metric(name: &str, value: f64) -> Metric {
+        Metric {
+            n: 30,
+            group: "Mordor".to_string(),
+            short_name: name.to_string(),
+            value: Some(value),
+            tags: vec!["site=mordor".to_string(), "critical".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_line() {
+        let statsd = Statsd::new("localhost", "jr", true, DEFAULT_MTU);
+        assert_eq!(statsd.server, "localhost:8125");
+        assert_eq!(
+            statsd.line(&metric("load avg", 0.5)).unwrap(),
+            "jr.load_avg:0.5|g|#group:Mordor,status:ok,every:30,site:mordor,critical"
+        );
+        let timed = Metric {
+            graph_type: Some("time".to_string()),
+            status: Status::Warning,
+            tags: Vec::new(),
+            ..metric("web", 120.0)
+        };
+        let plain = Statsd::new("localhost:9125", "", false, DEFAULT_MTU);
+        assert_eq!(plain.line(&timed).unwrap(), "web:120|ms");
+        assert_eq!(
+            statsd.line(&timed).unwrap(),
+            "jr.web:120|ms|#group:Mordor,status:warning,every:30"
+        );
+        assert_eq!(statsd.line(&Metric::default()), None);
+
+        let sampled = Statsd {
+            sample_rate: 0.25,
+            ..Statsd::new("localhost", "jr", true, DEFAULT_MTU)
+        };
+        assert_eq!(
+            sampled.line(&timed).unwrap(),
+            "jr.web:120|ms|@0.25|#group:Mordor,status:warning,every:30"
+        );
+    }
+
+    #[test]
+    fn test_sampling() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut statsd = Statsd {
+            sample_rate: 0.5,
+            ..Statsd::new(&address, "jr", false, 60000)
+        };
+        for i in 0..1000 {
+            statsd.send(&metric("a", i as f64)).unwrap();
+        }
+        // About half of them, far enough from 500 to never fail by chance
+        let sent = statsd.buffer.lines().count();
+        assert!((350..650).contains(&sent), "{} sent", sent);
+        assert!(statsd.buffer.lines().all(|line| line.ends_with("|g|@0.5")));
+    }
+
+    fn receive(server: &UdpSocket) -> String {
+        let mut packet = [0; 2048];
+        let (len, _) = server.recv_from(&mut packet).unwrap();
+        String::from_utf8_lossy(&packet[..len]).to_string()
+    }
+
+    #[test]
+    fn test_batching() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let mut statsd = Statsd::new(&address, "jr", false, 20);
+
+        statsd.send(&metric("a", 1.0)).unwrap(); // jr.a:1|g, 8 bytes
+        statsd.send(&metric("b", 2.0)).unwrap(); // 17 bytes with the newline
+        statsd.send(&metric("c", 3.0)).unwrap(); // Doesn't fit
+        assert_eq!(receive(&server), "jr.a:1|g\njr.b:2|g");
+        statsd.flush().unwrap();
+        assert_eq!(receive(&server), "jr.c:3|g");
+        statsd.flush().unwrap(); // Nothing left
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let Ok(server) = UdpSocket::bind("[::1]:0") else {
+            return; // No IPv6 here
+        };
+        let port = server.local_addr().unwrap().port();
+        let mut statsd = Statsd::new(&format!("[::1]:{}", port), "jr", false, DEFAULT_MTU);
+        statsd.send(&metric("a", 1.0)).unwrap();
+        statsd.flush().unwrap();
+        assert_eq!(receive(&server), "jr.a:1|g");
+    }
+}
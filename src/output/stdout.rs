@@ -1,6 +1,31 @@
+use std::error::Error;
+
+use crate::output::Output;
 use crate::types::Metric;
 
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn send(&mut self, metric: &Metric) -> Result<(), Box<dyn Error>> {
+        run(metric);
+        Ok(())
+    }
+
+    fn render(&self, metric: &Metric) -> Option<String> {
+        Some(format(metric))
+    }
+}
+
 pub fn run(metric: &Metric) {
+    println!("{}", format(metric));
+}
+
+/// The line printed for `metric`.
+pub fn format(metric: &Metric) -> String {
     let every = if metric.once { -1 } else { metric.n as i64 };
     let mut output = format!(
         "Value: {:.2} {:?}, message: {}, status: {}, every: {}",
@@ -19,9 +44,14 @@ pub fn run(metric: &Metric) {
         output.push_str(&format!(", max: {}", max_value));
     }
 
+    if metric.attempt > 0 {
+        let state = if metric.soft { "soft" } else { "hard" };
+        output.push_str(&format!(", attempt: {} ({})", metric.attempt, state));
+    }
+
     if let Some(graph_type) = &metric.graph_type {
         output.push_str(&format!(", graph_type: {}", graph_type));
     }
 
-    println!("{}", output);
+    output
 }
@@ -0,0 +1,283 @@
+// Calendar-based scheduling: cron expressions and active windows, evaluated in a timezone.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+
+/// How many missed cron runs are counted before giving up, after a long suspend.
+const MAX_MISSED: u32 = 1000;
+
+/// How many runs the period of a cron schedule is measured over.
+const PERIOD_RUNS: usize = 100;
+
+/// A cron schedule. On top of what the `cron` crate takes (seconds first, optional
+/// year, `@daily`...), the classic five fields `minute hour day month weekday` work.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    expr: String,
+    schedule: cron::Schedule,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Cron, String> {
+        let expr = expr.trim();
+        let full = if !expr.starts_with('@') && expr.split_whitespace().count() == 5 {
+            format!("0 {}", expr)
+        } else {
+            expr.to_string()
+        };
+        match cron::Schedule::from_str(&full) {
+            Ok(schedule) => Ok(Cron {
+                expr: expr.to_string(),
+                schedule,
+            }),
+            Err(e) => Err(format!("Invalid cron expression '{}': {}", expr, e)),
+        }
+    }
+
+    /// The first run strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>, tz: Tz) -> Option<DateTime<Utc>> {
+        self.schedule
+            .after(&after.with_timezone(&tz))
+            .next()
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    /// The shortest time between two runs, as an approximate interval. It's
+    /// measured over the first runs after the epoch rather than after now, so that
+    /// it doesn't depend on when jr starts.
+    pub fn period(&self) -> Duration {
+        let runs: Vec<DateTime<Utc>> = self
+            .schedule
+            .after(&DateTime::UNIX_EPOCH)
+            .take(PERIOD_RUNS)
+            .collect();
+        runs.windows(2)
+            .filter_map(|pair| (pair[1] - pair[0]).to_std().ok())
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// The first run after `now` following `due`, and how many runs were passed on
+    /// the way. Works like the interval version in the scheduler.
+    pub fn next_due(&self, due: Instant, now: Instant, tz: Tz) -> (Instant, u32) {
+        let now_utc = Utc::now();
+        let to_utc = |at: Instant| match chrono::Duration::from_std(now.duration_since(at)) {
+            Ok(ago) => now_utc - ago,
+            Err(_) => now_utc,
+        };
+        let to_instant = |at: DateTime<Utc>| now + (at - now_utc).to_std().unwrap_or_default();
+
+        let mut missed = 0;
+        let mut next = self.next_after(to_utc(due), tz);
+        while let Some(at) = next {
+            if at > now_utc || missed >= MAX_MISSED {
+                break;
+            }
+            missed += 1;
+            next = self.next_after(at, tz);
+        }
+        match next {
+            Some(at) => (to_instant(at), missed),
+            // A schedule with no run left (e.g. a past year): never due again
+            None => (now + Duration::from_secs(u32::MAX as u64), missed),
+        }
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+/// The days and hours during which a check runs at its normal interval, e.g.
+/// `Mon-Fri 09:00-18:00`. Without days, every day. The end may be past midnight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    pub fn contains(&self, at: DateTime<Utc>, tz: Tz) -> bool {
+        let local = at.with_timezone(&tz);
+        let time = local.time();
+        let today = self.days[local.weekday().num_days_from_monday() as usize];
+        if self.start <= self.end {
+            today && time >= self.start && time < self.end
+        } else {
+            let yesterday = self.days[local.weekday().pred().num_days_from_monday() as usize];
+            (today && time >= self.start) || (yesterday && time < self.end)
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.days != [true; 7] {
+            write!(f, "{} ", format_days(&self.days))?;
+        }
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid active window '{}'", s);
+        let mut words = s.split_whitespace();
+        let (days, hours) = match (words.next(), words.next(), words.next()) {
+            (Some(hours), None, None) => ([true; 7], hours),
+            (Some(days), Some(hours), None) => (parse_days(days).ok_or_else(invalid)?, hours),
+            _ => return Err(invalid()),
+        };
+        let (start, end) = hours.split_once('-').ok_or_else(invalid)?;
+        let parse_time = |t: &str| NaiveTime::parse_from_str(t, "%H:%M").map_err(|_| invalid());
+        Ok(Window {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+/// Parses days like `Mon-Fri`, `Sat,Sun` or `Mon-Wed,Fri`.
+fn parse_days(spec: &str) -> Option<[bool; 7]> {
+    let mut days = [false; 7];
+    for part in spec.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (
+                first.parse::<Weekday>().ok()?,
+                last.parse::<Weekday>().ok()?,
+            ),
+            None => {
+                let day = part.parse::<Weekday>().ok()?;
+                (day, day)
+            }
+        };
+        let mut day = first;
+        loop {
+            days[day.num_days_from_monday() as usize] = true;
+            if day == last {
+                break;
+            }
+            day = day.succ();
+        }
+    }
+    Some(days)
+}
+
+/// Formats days the way `parse_days` reads them, with ranges for consecutive days.
+fn format_days(days: &[bool; 7]) -> String {
+    let mut parts = Vec::new();
+    let mut day = 0;
+    while day < 7 {
+        if !days[day] {
+            day += 1;
+            continue;
+        }
+        let first = day;
+        while day + 1 < 7 && days[day + 1] {
+            day += 1;
+        }
+        let name = |d: usize| {
+            Weekday::try_from(d as u8)
+                .map(|w| w.to_string())
+                .unwrap_or_default()
+        };
+        if first == day {
+            parts.push(name(day));
+        } else {
+            parts.push(format!("{}-{}", name(first), name(day)));
+        }
+        day += 1;
+    }
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_cron_five_fields() {
+        let cron = Cron::parse("15 0,6 * * *").unwrap();
+        let after = Utc.with_ymd_and_hms(2025, 3, 10, 1, 0, 0).unwrap();
+        assert_eq!(
+            cron.next_after(after, Tz::UTC),
+            Some(Utc.with_ymd_and_hms(2025, 3, 10, 6, 15, 0).unwrap())
+        );
+        assert_eq!(cron.period(), Duration::from_secs(6 * 3600));
+        let weekdays = Cron::parse("0 9 * * Mon-Fri").unwrap();
+        assert_eq!(weekdays.period(), Duration::from_secs(24 * 3600));
+        assert!(Cron::parse("61 * * * *").is_err());
+        assert!(Cron::parse("@daily").is_ok());
+    }
+
+    #[test]
+    fn test_cron_timezone() {
+        // 00:15 in Buenos Aires is 03:15 UTC
+        let cron = Cron::parse("15 0 * * *").unwrap();
+        let after = Utc.with_ymd_and_hms(2025, 3, 10, 1, 0, 0).unwrap();
+        let tz: Tz = "America/Argentina/Buenos_Aires".parse().unwrap();
+        assert_eq!(
+            cron.next_after(after, tz),
+            Some(Utc.with_ymd_and_hms(2025, 3, 10, 3, 15, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_cron_next_due() {
+        let cron = Cron::parse("* * * * * *").unwrap(); // every second
+        let now = Instant::now();
+        let (next, missed) = cron.next_due(now - Duration::from_millis(3500), now, Tz::UTC);
+        assert!(next > now && next <= now + Duration::from_secs(1));
+        assert!((2..=4).contains(&missed));
+    }
+
+    #[test]
+    fn test_window() {
+        let window: Window = "Mon-Fri 09:00-18:00".parse().unwrap();
+        // 2025-03-10 is a Monday
+        let monday_noon = Utc.with_ymd_and_hms(2025, 3, 10, 12, 0, 0).unwrap();
+        let monday_night = Utc.with_ymd_and_hms(2025, 3, 10, 20, 0, 0).unwrap();
+        let saturday_noon = Utc.with_ymd_and_hms(2025, 3, 15, 12, 0, 0).unwrap();
+        assert!(window.contains(monday_noon, Tz::UTC));
+        assert!(!window.contains(monday_night, Tz::UTC));
+        assert!(!window.contains(saturday_noon, Tz::UTC));
+
+        // 12:00 UTC is 09:00 in Buenos Aires
+        let tz: Tz = "America/Argentina/Buenos_Aires".parse().unwrap();
+        assert!(window.contains(monday_noon, tz));
+        assert!(!window.contains(monday_noon - chrono::Duration::hours(1), tz));
+
+        // Overnight windows belong to the day they start
+        let night: Window = "Fri 22:00-02:00".parse().unwrap();
+        let saturday_one_am = Utc.with_ymd_and_hms(2025, 3, 15, 1, 0, 0).unwrap();
+        assert!(night.contains(saturday_one_am, Tz::UTC));
+        assert!(!night.contains(saturday_one_am + chrono::Duration::days(1), Tz::UTC));
+
+        let every_day: Window = "08:00-20:00".parse().unwrap();
+        assert!(every_day.contains(saturday_noon, Tz::UTC));
+
+        assert_eq!(window.to_string(), "Mon-Fri 09:00-18:00");
+        assert_eq!(every_day.to_string(), "08:00-20:00");
+        let odd_days: Window = "Mon,Wed-Thu,Sun 09:00-18:00".parse().unwrap();
+        assert_eq!(odd_days.to_string(), "Mon,Wed-Thu,Sun 09:00-18:00");
+
+        assert!("Mon-Fri".parse::<Window>().is_err());
+        assert!("Someday 09:00-18:00".parse::<Window>().is_err());
+        assert!("09:00-25:00".parse::<Window>().is_err());
+    }
+}
@@ -0,0 +1,1029 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::output::Outputs;
+use crate::types::{CatchUp, Metric, Settings, Splay, Status};
+use crate::worker::process::RunHandle;
+use crate::worker::Worker;
+
+pub mod calendar;
+
+mod pool;
+use pool::{Finished, Job, Pool};
+
+mod splay;
+pub mod timeline;
+
+pub const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+/// How often the scheduler reports its own lag.
+const LAG_REPORT_EVERY: Duration = Duration::from_secs(60);
+
+/// How long running checks are waited for when jr is stopped, by default.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often checks waiting for a thread are looked at, to time them once they start.
+const TIMEOUT_POLL: Duration = Duration::from_secs(1);
+
+/// A configured check. `worker` is `None` while the check is running, which is
+/// what keeps a check from overlapping with its own previous run.
+struct Check {
+    metric: Metric,
+    worker: Option<Box<dyn Worker>>,
+    /// Missed runs still to be made, see `CatchUp`.
+    pending: u32,
+    /// When the check is next due, before jitter.
+    next_due: Instant,
+    /// The queued due time, with jitter.
+    queued: Instant,
+    /// When a failed attempt is retried, if it is. A retry that is due while a
+    /// timed out run still holds the worker starts when the worker is handed back.
+    retry_at: Option<Instant>,
+    /// The due time of the last run that was started.
+    last_due: Option<Instant>,
+    /// The current or last run.
+    handle: RunHandle,
+    /// Whether the current run timed out. Its result is dropped when it comes.
+    timed_out: bool,
+}
+
+impl Check {
+    fn new(metric: Metric, worker: Box<dyn Worker>) -> Self {
+        Check {
+            metric,
+            worker: Some(worker),
+            pending: 0,
+            next_due: Instant::now(),
+            queued: Instant::now(),
+            retry_at: None,
+            last_due: None,
+            handle: RunHandle::default(),
+            timed_out: false,
+        }
+    }
+}
+
+/// Runs due checks on a bounded pool of threads and sends their results to the outputs.
+///
+/// Each check has its own next due time, computed from the previous due time rather
+/// than from when it actually ran, so the schedule doesn't drift. Splay and jitter
+/// move the actual start of a run, never the due times. Checks with a cron schedule
+/// are due at the times it gives instead, and checks with an active window only run
+/// inside it (or at a longer interval outside it).
+///
+/// Checks are identified by an id that is never reused, so that runs and due times
+/// of checks removed by a reload can be told apart.
+pub struct Scheduler {
+    checks: BTreeMap<usize, Check>,
+    next_id: usize,
+    due: BinaryHeap<Reverse<(Instant, usize)>>,
+    pool: Pool,
+    events: Receiver<Event>,
+    sender: Sender<Event>,
+    lag: Lag,
+    splay: Splay,
+    splay_max: Option<Duration>,
+    shutdown_timeout: Duration,
+    /// When jr is stopping, how long running checks are still waited for.
+    stopping: Option<Instant>,
+}
+
+/// What the scheduler waits for, besides due times.
+enum Event {
+    /// A check finished running.
+    Finished(Box<Finished>),
+    /// New checks replace the current ones.
+    Reload(Vec<(Metric, Box<dyn Worker>)>),
+    /// jr is asked to exit.
+    Stop,
+}
+
+/// Controls a running scheduler from another thread, e.g. on signals.
+#[derive(Clone)]
+pub struct Controller(Sender<Event>);
+
+impl Controller {
+    /// The first call lets the running checks finish, for a while; the second one
+    /// kills them right away.
+    pub fn stop(&self) {
+        let _ = self.0.send(Event::Stop);
+    }
+
+    /// Replaces the checks. Checks whose configuration didn't change keep their
+    /// schedule and state, new ones start and the others are stopped.
+    pub fn reload(&self, checks: Vec<(Metric, Box<dyn Worker>)>) {
+        let _ = self.0.send(Event::Reload(checks));
+    }
+}
+
+/// The worst lag seen since the last report, reported as the `jr_scheduler_lag` metric
+/// to the outputs given before the first `Group`, if any.
+struct Lag {
+    max: Duration,
+    next_report: Instant,
+    outputs: Vec<String>,
+}
+
+impl Scheduler {
+    pub fn new(checks: Vec<(Metric, Box<dyn Worker>)>, settings: &Settings) -> Self {
+        let (sender, events) = channel();
+        let max_concurrency = settings.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY);
+        // Only sent where outputs are configured, so that installs relying on the
+        // default outputs don't get a series they didn't ask for
+        let outputs = settings.outputs.clone().unwrap_or_default();
+        let checks: BTreeMap<usize, Check> = checks
+            .into_iter()
+            .map(|(metric, worker)| Check::new(metric, worker))
+            .enumerate()
+            .collect();
+        Scheduler {
+            next_id: checks.len(),
+            checks,
+            due: BinaryHeap::new(),
+            pool: Pool::new(max_concurrency, sender.clone()),
+            events,
+            sender,
+            lag: Lag {
+                max: Duration::ZERO,
+                next_report: Instant::now() + LAG_REPORT_EVERY,
+                outputs,
+            },
+            splay: settings.splay.unwrap_or_default(),
+            splay_max: settings.splay_max,
+            shutdown_timeout: settings
+                .shutdown_timeout
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            stopping: None,
+        }
+    }
+
+    pub fn controller(&self) -> Controller {
+        Controller(self.sender.clone())
+    }
+
+    /// Runs the checks until jr is stopped, or only once if any check asks for it.
+    /// When running once, the results are sent in configuration order at the end.
+    ///
+    /// Once stopped, no check is started anymore. The running ones are waited for
+    /// until the shutdown timeout, then killed.
+    pub fn run(&mut self, outputs: &mut Outputs) {
+        let once = self.checks.values().any(|c| c.metric.once);
+        let start = Instant::now();
+        let ids: Vec<usize> = self.checks.keys().copied().collect();
+        for id in ids {
+            self.start(id, start, once);
+        }
+        self.lag.next_report = start + LAG_REPORT_EVERY;
+
+        loop {
+            let now = Instant::now();
+            while let Some(&Reverse((due, id))) = self.due.peek() {
+                if due > now || self.stopping.is_some() {
+                    break;
+                }
+                self.due.pop();
+                let Some(check) = self.checks.get_mut(&id) else {
+                    continue; // Removed by a reload
+                };
+                if check.retry_at == Some(due) {
+                    self.dispatch(id, due);
+                    continue;
+                }
+                if check.queued != due {
+                    continue; // A retry that was cancelled
+                }
+                if !once && !self.in_window(id) {
+                    // Not a missed run: the check isn't meant to run now
+                    self.schedule_next(id, now, true);
+                    continue;
+                }
+                let started = self.dispatch(id, due);
+                if !once {
+                    self.schedule_next(id, now, started);
+                }
+            }
+
+            for id in self.time_out(now) {
+                self.retry_if_soft(id, now, once);
+                if !once {
+                    send(&self.checks[&id].metric, outputs);
+                }
+            }
+
+            if let Some(deadline) = self.stopping {
+                if self.running() == 0 {
+                    break;
+                }
+                if now >= deadline {
+                    self.kill_running();
+                    break;
+                }
+            }
+
+            // A soft result still has a retry to make, maybe waiting for its worker
+            if once && self.running() == 0 && self.due.is_empty() && !self.soft() {
+                for check in self.checks.values() {
+                    send(&check.metric, outputs);
+                }
+                break;
+            }
+
+            if !once && self.stopping.is_none() && now >= self.lag.next_report {
+                send(&self.lag.report(now), outputs);
+            }
+
+            // Outputs that batch results send them before waiting
+            idle(outputs);
+
+            // Handle results until something else is due
+            let mut wake = self.lag.next_report;
+            if let Some(&Reverse((due, _))) = self.due.peek() {
+                wake = wake.min(due);
+            }
+            if let Some(deadline) = self.next_timeout(now) {
+                wake = wake.min(deadline);
+            }
+            if let Some(deadline) = self.stopping {
+                wake = wake.min(deadline);
+            }
+            match self
+                .events
+                .recv_timeout(wake.saturating_duration_since(now))
+            {
+                Ok(Event::Stop) if self.stopping.is_some() => {
+                    self.kill_running();
+                    break;
+                }
+                Ok(Event::Stop) => {
+                    eprintln!(
+                        "Stopping, waiting up to {}s for {} running check(s)",
+                        self.shutdown_timeout.as_secs_f64(),
+                        self.running()
+                    );
+                    self.stopping = Some(now + self.shutdown_timeout);
+                }
+                Ok(Event::Reload(checks)) => {
+                    if self.stopping.is_none() {
+                        self.reload(checks, once);
+                    }
+                }
+                Ok(Event::Finished(finished)) => {
+                    let id = finished.id;
+                    if !self.finish(finished) {
+                        self.start_late_retry(id);
+                        continue;
+                    }
+                    self.retry_if_soft(id, Instant::now(), once);
+                    if !once {
+                        send(&self.checks[&id].metric, outputs);
+                        if self.stopping.is_none() {
+                            self.catch_up(id);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// The last result of each check, in configuration order.
+    pub fn results(&self) -> Vec<&Metric> {
+        self.checks.values().map(|check| &check.metric).collect()
+    }
+
+    /// Calls `teardown` on the workers of the checks that aren't running.
+    pub fn teardown(&mut self) {
+        for check in self.checks.values_mut() {
+            if let Some(worker) = &mut check.worker {
+                worker.teardown();
+            }
+        }
+    }
+
+    /// Queues the first run of check `id`.
+    fn start(&mut self, id: usize, now: Instant, once: bool) {
+        let check = self.checks.get_mut(&id).expect("a configured check");
+        check.next_due = now;
+        if !once {
+            if let Some(cron) = &check.metric.cron {
+                check.next_due = cron.next_due(now, now, check.metric.timezone).0;
+            } else {
+                let period = Duration::from_secs(check.metric.n);
+                check.next_due +=
+                    splay::offset(self.splay, &check.metric.short_name, period, self.splay_max);
+            }
+        }
+        self.push_due(id);
+    }
+
+    /// Replaces the checks with `checks`, keeping the ones whose configuration didn't change.
+    fn reload(&mut self, checks: Vec<(Metric, Box<dyn Worker>)>, once: bool) {
+        let mut kept = BTreeSet::new();
+        let mut added = Vec::new();
+        for (metric, mut worker) in checks {
+            let same = self
+                .checks
+                .iter()
+                .find(|(id, check)| !kept.contains(*id) && check.metric.same_config(&metric));
+            match same {
+                Some((&id, _)) => {
+                    kept.insert(id);
+                    worker.teardown();
+                }
+                None => added.push((metric, worker)),
+            }
+        }
+
+        let removed: Vec<usize> = self
+            .checks
+            .keys()
+            .filter(|id| !kept.contains(*id))
+            .copied()
+            .collect();
+        for id in &removed {
+            if let Some(mut check) = self.checks.remove(id) {
+                check.handle.kill();
+                if let Some(worker) = &mut check.worker {
+                    worker.teardown();
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let count = added.len();
+        for (metric, worker) in added {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.checks.insert(id, Check::new(metric, worker));
+            self.start(id, now, once);
+        }
+        eprintln!(
+            "Reloaded the configuration: {} check(s) added, {} removed, {} unchanged",
+            count,
+            removed.len(),
+            kept.len()
+        );
+    }
+
+    /// Starts check `id` unless it's still running. Returns whether it was started.
+    /// A started run is also the retry of the previous attempt, if one was queued.
+    fn dispatch(&mut self, id: usize, due: Instant) -> bool {
+        let Some(check) = self.checks.get_mut(&id) else {
+            return false;
+        };
+        match check.worker.take() {
+            Some(worker) => {
+                check.last_due = Some(check.next_due);
+                check.retry_at = None;
+                check.handle = RunHandle::default();
+                check.timed_out = false;
+                let mut metric = check.metric.without_result();
+                metric.timeout = Some(timeout(&metric));
+                self.pool.submit(Job {
+                    id,
+                    due,
+                    metric,
+                    worker,
+                    handle: check.handle.clone(),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether check `id` may run at its current due time, given its active window.
+    fn in_window(&self, id: usize) -> bool {
+        let check = &self.checks[&id];
+        let Some(active) = &check.metric.active else {
+            return true;
+        };
+        if active.contains(Utc::now(), check.metric.timezone) {
+            return true;
+        }
+        match (check.metric.inactive_every, check.last_due) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(every), Some(last_due)) => {
+                check.next_due.duration_since(last_due) >= Duration::from_secs(every)
+            }
+        }
+    }
+
+    /// Computes the next due time of check `id`. `handled` tells whether the run
+    /// that was due either started or wasn't meant to run; otherwise it's missed.
+    fn schedule_next(&mut self, id: usize, now: Instant, handled: bool) {
+        let check = self.checks.get_mut(&id).expect("a configured check");
+        let (next, missed) = match &check.metric.cron {
+            Some(cron) => cron.next_due(check.next_due, now, check.metric.timezone),
+            None => next_due(check.next_due, Duration::from_secs(check.metric.n), now),
+        };
+        check.next_due = next;
+        let missed = missed + u32::from(!handled);
+        let (pending, skipped) =
+            apply_catch_up(check.metric.catch_up, check.pending, handled, missed);
+        check.pending = pending;
+        if skipped > 0 {
+            eprintln!(
+                "Skipped {} run(s) of check '{}'",
+                skipped, check.metric.short_name
+            );
+        }
+        self.push_due(id);
+    }
+
+    /// Queues the next run of check `id`, at its due time plus jitter.
+    fn push_due(&mut self, id: usize) {
+        let check = self.checks.get_mut(&id).expect("a configured check");
+        let period = Duration::from_secs(check.metric.n);
+        let at = check.next_due + splay::jitter(check.metric.jitter, period);
+        check.queued = at;
+        self.due.push(Reverse((at, id)));
+    }
+
+    /// Queues a retry of check `id` if its last attempt was a soft failure. Without
+    /// a retry interval, the next regular run is the retry, except when running once.
+    fn retry_if_soft(&mut self, id: usize, now: Instant, once: bool) {
+        let check = self.checks.get_mut(&id).expect("a configured check");
+        check.retry_at = None;
+        if !check.metric.soft {
+            return;
+        }
+        let at = match check.metric.retry_interval {
+            Some(interval) => now + interval,
+            None if once => now,
+            None => return,
+        };
+        check.retry_at = Some(at);
+        self.due.push(Reverse((at, id)));
+    }
+
+    /// Starts the retry of check `id` if it was due while the worker was busy.
+    fn start_late_retry(&mut self, id: usize) {
+        let Some(check) = self.checks.get(&id) else {
+            return;
+        };
+        if let Some(at) = check.retry_at.filter(|at| *at <= Instant::now()) {
+            self.dispatch(id, at);
+        }
+    }
+
+    /// Starts a pending run of check `id`, if it has one.
+    fn catch_up(&mut self, id: usize) {
+        let check = self.checks.get_mut(&id).expect("a configured check");
+        if check.pending > 0 {
+            check.pending -= 1;
+            self.dispatch(id, Instant::now());
+        }
+    }
+
+    /// Kills the runs that took longer than their timeout and records a timeout
+    /// result for them. Returns the checks that timed out.
+    fn time_out(&mut self, now: Instant) -> Vec<usize> {
+        let mut timed_out = Vec::new();
+        for (&id, check) in self.checks.iter_mut() {
+            if check.worker.is_some() || check.timed_out {
+                continue;
+            }
+            let Some(started) = check.handle.started() else {
+                continue; // Still waiting for a free thread
+            };
+            let timeout = timeout(&check.metric);
+            if now.saturating_duration_since(started) < timeout {
+                continue;
+            }
+            check.handle.kill();
+            check.timed_out = true;
+            check.metric = retry(&check.metric, timed_out_metric(&check.metric, timeout));
+            timed_out.push(id);
+        }
+        timed_out
+    }
+
+    /// Kills the checks still running when jr exits.
+    fn kill_running(&mut self) {
+        let running: Vec<&Check> = self
+            .checks
+            .values()
+            .filter(|c| c.worker.is_none() && !c.timed_out)
+            .collect();
+        if running.is_empty() {
+            return;
+        }
+        for check in &running {
+            check.handle.kill();
+        }
+        eprintln!("Killed {} check(s) still running", running.len());
+    }
+
+    /// When the next running check times out. Checks waiting for a thread are
+    /// looked at again after `TIMEOUT_POLL`.
+    fn next_timeout(&self, now: Instant) -> Option<Instant> {
+        self.checks
+            .values()
+            .filter(|c| c.worker.is_none() && !c.timed_out)
+            .map(|c| match c.handle.started() {
+                Some(started) => started + timeout(&c.metric),
+                None => now + TIMEOUT_POLL,
+            })
+            .min()
+    }
+
+    /// Stores the result of a run. Returns false if the run had already timed out,
+    /// in which case only the worker is kept, or if the check was removed since.
+    fn finish(&mut self, finished: Box<Finished>) -> bool {
+        self.lag.max = self.lag.max.max(finished.lag);
+        let Some(check) = self.checks.get_mut(&finished.id) else {
+            let mut worker = finished.worker;
+            worker.teardown();
+            return false;
+        };
+        check.worker = Some(finished.worker);
+        if check.timed_out {
+            return false;
+        }
+        // The run got the effective timeout, keep the configured one
+        let mut metric = Metric {
+            timeout: check.metric.timeout,
+            ..finished.metric
+        };
+        metric.apply_thresholds();
+        check.metric = retry(&check.metric, metric);
+        true
+    }
+
+    /// Whether any check has a soft result, so a retry to make.
+    fn soft(&self) -> bool {
+        self.checks.values().any(|c| c.metric.soft)
+    }
+
+    /// Checks still running, not counting the ones that timed out.
+    fn running(&self) -> usize {
+        self.checks
+            .values()
+            .filter(|c| c.worker.is_none() && !c.timed_out)
+            .count()
+    }
+}
+
+impl Lag {
+    fn report(&mut self, now: Instant) -> Metric {
+        let lag = self.max.as_millis();
+        self.max = Duration::ZERO;
+        self.next_report = now + LAG_REPORT_EVERY;
+        Metric {
+            n: LAG_REPORT_EVERY.as_secs(),
+            function: "scheduler".to_string(),
+            group: "Default".to_string(),
+            short_name: "jr_scheduler_lag".to_string(),
+            outputs: self.outputs.clone(),
+            value: Some(lag as f64),
+            units: Some("ms".to_string()),
+            message: Some(format!(
+                "Checks started up to {}ms late in the last {}s",
+                lag,
+                LAG_REPORT_EVERY.as_secs()
+            )),
+            graph_value: Some(lag as i64),
+            graph_type: Some("time".to_string()),
+            graph_short_name: Some("jr_scheduler_lag".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// How long a run of `metric` may take: its timeout, or else its interval.
+fn timeout(metric: &Metric) -> Duration {
+    metric
+        .timeout
+        .unwrap_or(Duration::from_secs(metric.n.max(1)))
+}
+
+/// The result reported for a run killed after `timeout`.
+fn timed_out_metric(metric: &Metric, timeout: Duration) -> Metric {
+    Metric {
+        value: Some(-1.0),
+        message: Some(format!("Timed out after {}s", timeout.as_secs_f64())),
+        graph_value: Some(-1),
+        graph_type: Some("time".to_string()),
+        graph_short_name: Some(metric.short_name.clone()),
+        status: Status::Timeout,
+        ..metric.without_result()
+    }
+}
+
+/// Counts the failed attempts of a check, given its `previous` result. A failure
+/// stays soft, keeping the previous status, until `retries` attempts in a row
+/// failed; then it's hard and reported as is.
+fn retry(previous: &Metric, mut metric: Metric) -> Metric {
+    if metric.status == Status::Ok {
+        metric.attempt = 0;
+        return metric;
+    }
+    metric.attempt = previous.attempt + 1;
+    if metric.attempt <= metric.retries {
+        metric.soft = true;
+        metric.message = Some(format!(
+            "Soft {}, attempt {}/{}: {}",
+            metric.status,
+            metric.attempt,
+            metric.retries + 1,
+            metric.message.as_deref().unwrap_or_default()
+        ));
+        metric.status = previous.status;
+    }
+    metric
+}
+
+/// The first due time after `now` following `due`, and how many due times were
+/// passed on the way.
+fn next_due(due: Instant, period: Duration, now: Instant) -> (Instant, u32) {
+    let next = due + period;
+    if next > now || period.is_zero() {
+        return (next, 0);
+    }
+    let missed = (now.duration_since(next).as_nanos() / period.as_nanos()) as u32 + 1;
+    (next + period * missed, missed)
+}
+
+/// Applies a catch-up policy to `missed` runs. Returns the new number of pending
+/// runs and how many runs were skipped.
+fn apply_catch_up(policy: CatchUp, pending: u32, handled: bool, missed: u32) -> (u32, u32) {
+    match policy {
+        CatchUp::Skip => (pending, missed),
+        // A run that just started already catches up
+        CatchUp::Once if handled || missed == 0 => (pending, 0),
+        CatchUp::Once => (1, 0),
+        CatchUp::All => (pending + missed, 0),
+    }
+}
+
+fn send(metric: &Metric, outputs: &mut Outputs) {
+    for error in outputs.send(metric) {
+        eprintln!("Check '{}': {}", metric.short_name, error);
+    }
+}
+
+fn idle(outputs: &mut Outputs) {
+    for error in outputs.idle() {
+        eprintln!("{}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Registry;
+    use crate::worker::process;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    struct Slow;
+
+    impl Worker for Slow {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        fn run(&mut self, mut metric: Metric) -> Metric {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+            sleep(Duration::from_millis(300));
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            metric.value = Some(1.0);
+            metric
+        }
+    }
+
+    #[test]
+    fn test_scheduler_runs_checks_concurrently() {
+        let checks = (0..4)
+            .map(|i| {
+                let metric = Metric {
+                    n: 1,
+                    once: true,
+                    short_name: format!("slow{}", i),
+                    outputs: Vec::new(),
+                    ..Default::default()
+                };
+                (metric, Box::new(Slow) as Box<dyn Worker>)
+            })
+            .collect();
+        let settings = Settings {
+            max_concurrency: Some(2),
+            ..Default::default()
+        };
+        let mut scheduler = Scheduler::new(checks, &settings);
+        let mut outputs = Outputs::new(Registry::default());
+
+        let start = Instant::now();
+        scheduler.run(&mut outputs);
+
+        // 4 checks of 300ms, 2 at a time
+        assert!(start.elapsed() < Duration::from_millis(1000));
+        assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 2);
+        assert!(scheduler
+            .checks
+            .values()
+            .all(|c| c.metric.value == Some(1.0) && c.metric.timeout.is_none()));
+        assert_eq!(scheduler.running(), 0);
+    }
+
+    struct Hung;
+
+    impl Worker for Hung {
+        fn name(&self) -> &'static str {
+            "hung"
+        }
+
+        fn run(&mut self, mut metric: Metric) -> Metric {
+            let mut child = process::spawn(Command::new("sleep").arg("30")).unwrap();
+            let _ = child.wait();
+            process::reaped(&child);
+            metric.value = Some(1.0);
+            metric
+        }
+    }
+
+    #[test]
+    fn test_scheduler_times_out_checks() {
+        let metric = Metric {
+            n: 10,
+            once: true,
+            short_name: "hung".to_string(),
+            outputs: Vec::new(),
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let mut scheduler = Scheduler::new(vec![(metric, Box::new(Hung))], &Settings::default());
+        let mut outputs = Outputs::new(Registry::default());
+
+        let start = Instant::now();
+        scheduler.run(&mut outputs);
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let check = &scheduler.checks[&0];
+        assert_eq!(check.metric.status, Status::Timeout);
+        assert_eq!(
+            check.metric.message.as_deref(),
+            Some("Timed out after 0.2s")
+        );
+        assert!(check.handle.killed());
+
+        // The killed run hands its worker back, and its late result is dropped
+        let Ok(Event::Finished(finished)) = scheduler.events.recv_timeout(Duration::from_secs(2))
+        else {
+            panic!("The killed run didn't finish");
+        };
+        assert!(!scheduler.finish(finished));
+        assert_eq!(scheduler.checks[&0].metric.status, Status::Timeout);
+        assert!(scheduler.checks[&0].worker.is_some());
+    }
+
+    struct Sleepy;
+
+    impl Worker for Sleepy {
+        fn name(&self) -> &'static str {
+            "sleepy"
+        }
+
+        fn run(&mut self, mut metric: Metric) -> Metric {
+            sleep(Duration::from_millis(300));
+            metric.value = Some(1.0);
+            metric
+        }
+    }
+
+    /// A scheduler running `worker` every hour, stopped after 100ms.
+    fn stopped_scheduler(worker: Box<dyn Worker>, shutdown_timeout: Duration) -> Scheduler {
+        let metric = Metric {
+            n: 3600,
+            outputs: Vec::new(),
+            timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let settings = Settings {
+            shutdown_timeout: Some(shutdown_timeout),
+            ..Default::default()
+        };
+        let mut scheduler = Scheduler::new(vec![(metric, worker)], &settings);
+        let controller = scheduler.controller();
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            controller.stop();
+        });
+        scheduler.run(&mut Outputs::new(Registry::default()));
+        scheduler
+    }
+
+    #[test]
+    fn test_stop_waits_for_running_checks() {
+        let start = Instant::now();
+        let scheduler = stopped_scheduler(Box::new(Sleepy), Duration::from_secs(5));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(scheduler.checks[&0].metric.value, Some(1.0));
+        assert!(!scheduler.checks[&0].handle.killed());
+    }
+
+    #[test]
+    fn test_stop_kills_checks_after_shutdown_timeout() {
+        let start = Instant::now();
+        let scheduler = stopped_scheduler(Box::new(Hung), Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(scheduler.checks[&0].worker.is_none());
+        assert!(scheduler.checks[&0].handle.killed());
+    }
+
+    #[test]
+    fn test_reload_keeps_unchanged_checks() {
+        let check = |name: &str, n: u64| -> (Metric, Box<dyn Worker>) {
+            let metric = Metric {
+                n,
+                short_name: name.to_string(),
+                outputs: Vec::new(),
+                ..Default::default()
+            };
+            (metric, Box::new(Sleepy))
+        };
+        let mut scheduler =
+            Scheduler::new(vec![check("a", 60), check("b", 60)], &Settings::default());
+        let next_due = Instant::now() + Duration::from_secs(42);
+        scheduler.checks.get_mut(&0).unwrap().next_due = next_due;
+
+        // A run of a, which got the effective timeout
+        let (mut metric, worker) = check("a", 60);
+        metric.timeout = Some(Duration::from_secs(60));
+        metric.value = Some(5.0);
+        let finished = Finished {
+            id: 0,
+            lag: Duration::ZERO,
+            metric,
+            worker,
+        };
+        assert!(scheduler.finish(Box::new(finished)));
+
+        scheduler.reload(vec![check("a", 60), check("b", 30), check("c", 60)], false);
+
+        let ids: Vec<usize> = scheduler.checks.keys().copied().collect();
+        assert_eq!(ids, vec![0, 2, 3]);
+        assert_eq!(scheduler.checks[&0].metric.value, Some(5.0));
+        assert_eq!(scheduler.checks[&0].next_due, next_due);
+        assert_eq!(scheduler.checks[&2].metric.short_name, "b");
+        assert_eq!(scheduler.checks[&2].metric.n, 30);
+        assert_eq!(scheduler.checks[&3].metric.short_name, "c");
+
+        // Due times of the removed check are ignored, as are its results
+        assert!(!scheduler.dispatch(1, Instant::now()));
+        let finished = Finished {
+            id: 1,
+            lag: Duration::ZERO,
+            metric: Metric::default(),
+            worker: Box::new(Sleepy),
+        };
+        assert!(!scheduler.finish(Box::new(finished)));
+    }
+
+    #[test]
+    fn test_retry() {
+        let failed = |status: Status| Metric {
+            retries: 2,
+            message: Some("HTTP error: 500".to_string()),
+            status,
+            ..Default::default()
+        };
+
+        let first = retry(&Metric::default(), failed(Status::Critical));
+        assert!(first.soft);
+        assert_eq!((first.status.as_str(), first.attempt), ("ok", 1));
+        assert_eq!(
+            first.message.as_deref(),
+            Some("Soft critical, attempt 1/3: HTTP error: 500")
+        );
+
+        let second = retry(&first, failed(Status::Timeout));
+        assert!(second.soft);
+        assert_eq!((second.status.as_str(), second.attempt), ("ok", 2));
+
+        let third = retry(&second, failed(Status::Critical));
+        assert!(!third.soft);
+        assert_eq!((third.status.as_str(), third.attempt), ("critical", 3));
+        assert_eq!(third.message.as_deref(), Some("HTTP error: 500"));
+
+        // Once hard, failures stay hard until the check recovers
+        let fourth = retry(&third, failed(Status::Critical));
+        assert!(!fourth.soft);
+        assert_eq!(fourth.attempt, 4);
+
+        let recovered = retry(&fourth, failed(Status::Ok));
+        assert_eq!((recovered.status.as_str(), recovered.attempt), ("ok", 0));
+        assert!(retry(&recovered, failed(Status::Critical)).soft);
+    }
+
+    static FLAKY_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Flaky;
+
+    impl Worker for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn run(&mut self, mut metric: Metric) -> Metric {
+            // Fails twice, then works
+            if FLAKY_RUNS.fetch_add(1, Ordering::SeqCst) < 2 {
+                metric.status = Status::Critical;
+            }
+            metric
+        }
+    }
+
+    #[test]
+    fn test_scheduler_retries_soft_failures() {
+        let metric = Metric {
+            n: 3600,
+            once: true,
+            short_name: "flaky".to_string(),
+            outputs: Vec::new(),
+            retries: 2,
+            retry_interval: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut scheduler = Scheduler::new(vec![(metric, Box::new(Flaky))], &Settings::default());
+        let mut outputs = Outputs::new(Registry::default());
+
+        let start = Instant::now();
+        scheduler.run(&mut outputs);
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(FLAKY_RUNS.load(Ordering::SeqCst), 3);
+        let metric = &scheduler.checks[&0].metric;
+        assert_eq!((metric.status.as_str(), metric.attempt), ("ok", 0));
+        assert!(!metric.soft);
+    }
+
+    #[test]
+    fn test_next_due() {
+        let start = Instant::now();
+        let period = Duration::from_secs(10);
+
+        // On time: the next run is one period after the previous due time
+        let (next, missed) = next_due(start, period, start + Duration::from_secs(3));
+        assert_eq!(next, start + Duration::from_secs(10));
+        assert_eq!(missed, 0);
+
+        // 25s late: the runs at 10s and 20s were missed
+        let (next, missed) = next_due(start, period, start + Duration::from_secs(25));
+        assert_eq!(next, start + Duration::from_secs(30));
+        assert_eq!(missed, 2);
+
+        // Exactly on the next due time counts as missed
+        let (next, missed) = next_due(start, period, start + Duration::from_secs(10));
+        assert_eq!(next, start + Duration::from_secs(20));
+        assert_eq!(missed, 1);
+    }
+
+    #[test]
+    fn test_apply_catch_up() {
+        assert_eq!(apply_catch_up(CatchUp::Skip, 0, false, 3), (0, 3));
+        assert_eq!(apply_catch_up(CatchUp::Once, 0, false, 3), (1, 0));
+        assert_eq!(apply_catch_up(CatchUp::Once, 0, true, 3), (0, 0));
+        assert_eq!(apply_catch_up(CatchUp::Once, 1, false, 1), (1, 0));
+        assert_eq!(apply_catch_up(CatchUp::All, 1, false, 3), (4, 0));
+        assert_eq!(apply_catch_up(CatchUp::All, 0, true, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_lag_report() {
+        let now = Instant::now();
+        let mut lag = Lag {
+            max: Duration::from_millis(1500),
+            next_report: now,
+            outputs: vec!["stdout".to_string()],
+        };
+        let metric = lag.report(now);
+        assert_eq!(metric.short_name, "jr_scheduler_lag");
+        assert_eq!(metric.value, Some(1500.0));
+        assert_eq!(metric.outputs, vec!["stdout"]);
+        assert_eq!(lag.max, Duration::ZERO);
+        assert_eq!(lag.next_report, now + LAG_REPORT_EVERY);
+
+        // Not sent anywhere unless outputs are configured
+        let scheduler = Scheduler::new(Vec::new(), &Settings::default());
+        assert!(scheduler.lag.outputs.is_empty());
+        let settings = Settings {
+            outputs: Some(vec!["statsd".to_string()]),
+            ..Default::default()
+        };
+        let scheduler = Scheduler::new(Vec::new(), &settings);
+        assert_eq!(scheduler.lag.outputs, vec!["statsd"]);
+    }
+}
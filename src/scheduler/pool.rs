@@ -0,0 +1,80 @@
+// A fixed-size pool of threads running checks.
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Event;
+use crate::types::Metric;
+use crate::worker::process::{self, RunHandle};
+use crate::worker::Worker;
+
+pub struct Job {
+    pub id: usize,
+    /// When the check was due, to measure how late it starts.
+    pub due: Instant,
+    pub metric: Metric,
+    pub worker: Box<dyn Worker>,
+    /// Lets the scheduler time the run and kill what it started.
+    pub handle: RunHandle,
+}
+
+/// A job that ran, with the resulting metric and the worker handed back.
+pub struct Finished {
+    pub id: usize,
+    /// How long after its due time the job started.
+    pub lag: Duration,
+    pub metric: Metric,
+    pub worker: Box<dyn Worker>,
+}
+
+/// Dropping the pool lets idle threads return. Threads still running a job
+/// aren't waited for, so a hung check can't block jr.
+pub struct Pool {
+    jobs: Sender<Job>,
+}
+
+impl Pool {
+    /// Starts `size` threads. Each finished job is sent to `results`.
+    pub fn new(size: usize, results: Sender<Event>) -> Pool {
+        let (jobs, queue) = channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..size.max(1) {
+            let queue = Arc::clone(&queue);
+            let results = results.clone();
+            thread::spawn(move || work(queue, results));
+        }
+        Pool { jobs }
+    }
+
+    /// Queues a job. It starts as soon as a thread is free.
+    pub fn submit(&self, job: Job) {
+        let _ = self.jobs.send(job);
+    }
+}
+
+fn work(queue: Arc<Mutex<Receiver<Job>>>, results: Sender<Event>) {
+    loop {
+        // The lock is released as soon as a job is received
+        let job = match queue.lock() {
+            Ok(queue) => queue.recv(),
+            Err(_) => return,
+        };
+        let Ok(mut job) = job else {
+            return; // The pool was dropped
+        };
+        let lag = Instant::now().saturating_duration_since(job.due);
+        job.handle.start();
+        let metric = job.worker.run(job.metric);
+        process::finish();
+        let finished = Finished {
+            id: job.id,
+            lag,
+            metric,
+            worker: job.worker,
+        };
+        if results.send(Event::Finished(Box::new(finished))).is_err() {
+            return;
+        }
+    }
+}
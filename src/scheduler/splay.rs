@@ -0,0 +1,84 @@
+// Startup offsets and per-run jitter, so checks don't all fire at the same time.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::types::Splay;
+
+/// How long after jr starts the first run of a check is due. The offset stays
+/// below `period` and `max`, if given.
+pub fn offset(splay: Splay, name: &str, period: Duration, max: Option<Duration>) -> Duration {
+    let range = match max {
+        Some(max) => period.min(max),
+        None => period,
+    };
+    match splay {
+        Splay::None => Duration::ZERO,
+        Splay::Random => range.mul_f64(random_fraction()),
+        Splay::Hash => range.mul_f64(fnv1a(name) as f64 / (u64::MAX as f64 + 1.0)),
+    }
+}
+
+/// A random delay of up to `max`, kept below `period` so a run never slips into the next one.
+pub fn jitter(max: Duration, period: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    max.min(period).mul_f64(random_fraction())
+}
+
+/// A random number in [0, 1). `RandomState` is seeded randomly by the standard library,
+/// which is good enough to spread checks around.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// FNV-1a, a hash that doesn't change between runs or Rust versions.
+fn fnv1a(name: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset() {
+        let period = Duration::from_secs(60);
+        assert_eq!(offset(Splay::None, "web", period, None), Duration::ZERO);
+
+        // Hash offsets are stable and differ between names
+        let web = offset(Splay::Hash, "web", period, None);
+        assert_eq!(web, offset(Splay::Hash, "web", period, None));
+        assert_ne!(web, offset(Splay::Hash, "db", period, None));
+        assert!(web < period);
+
+        let max = Duration::from_secs(5);
+        for name in ["a", "b", "c", "d"] {
+            assert!(offset(Splay::Hash, name, period, Some(max)) < max);
+            assert!(offset(Splay::Random, name, period, Some(max)) < max);
+        }
+    }
+
+    #[test]
+    fn test_jitter() {
+        assert_eq!(
+            jitter(Duration::ZERO, Duration::from_secs(10)),
+            Duration::ZERO
+        );
+        for _ in 0..10 {
+            assert!(
+                jitter(Duration::from_secs(2), Duration::from_secs(10)) < Duration::from_secs(2)
+            );
+            assert!(
+                jitter(Duration::from_secs(20), Duration::from_secs(1)) < Duration::from_secs(1)
+            );
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// When checks would run, computed without running them, for `jr list` and
+// `jr schedule`.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use super::splay;
+use crate::types::{Metric, Settings};
+
+/// The times a check is due at if jr starts at `from`, up to `until`. They're
+/// computed like the scheduler does: splay and active windows are applied, jitter
+/// isn't. With `Splay random`, they're one of the possible schedules.
+pub struct DueTimes<'a> {
+    metric: &'a Metric,
+    next: Option<DateTime<Utc>>,
+    until: DateTime<Utc>,
+    /// The last time the check was due and allowed to run by its window.
+    last: Option<DateTime<Utc>>,
+}
+
+impl<'a> DueTimes<'a> {
+    pub fn new(
+        metric: &'a Metric,
+        settings: &Settings,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Self {
+        let next = if metric.once {
+            Some(from)
+        } else if let Some(cron) = &metric.cron {
+            cron.next_after(from, metric.timezone)
+        } else {
+            let period = Duration::from_secs(metric.n);
+            let splay = settings.splay.unwrap_or_default();
+            let offset = splay::offset(splay, &metric.short_name, period, settings.splay_max);
+            chrono::Duration::from_std(offset)
+                .ok()
+                .map(|offset| from + offset)
+        };
+        DueTimes {
+            metric,
+            next,
+            until,
+            last: None,
+        }
+    }
+
+    /// Whether the check runs when due at `at`, given its active window.
+    fn in_window(&self, at: DateTime<Utc>) -> bool {
+        let Some(active) = &self.metric.active else {
+            return true;
+        };
+        if active.contains(at, self.metric.timezone) {
+            return true;
+        }
+        match (self.metric.inactive_every, self.last) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(every), Some(last)) => (at - last).num_seconds() >= every as i64,
+        }
+    }
+
+    fn after(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.metric.once {
+            return None;
+        }
+        match &self.metric.cron {
+            Some(cron) => cron.next_after(at, self.metric.timezone),
+            None => match self.metric.n {
+                0 => None,
+                n => Some(at + chrono::Duration::seconds(n as i64)),
+            },
+        }
+    }
+}
+
+impl Iterator for DueTimes<'_> {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        loop {
+            let at = self.next.filter(|at| *at < self.until)?;
+            self.next = self.after(at);
+            if self.in_window(at) {
+                self.last = Some(at);
+                return Some(at);
+            }
+        }
+    }
+}
+
+/// When each of `metrics` would run between `from` and `until`, in order, with the
+/// index of the check.
+pub fn timeline(
+    metrics: &[Metric],
+    settings: &Settings,
+    from: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, usize)> {
+    let mut timeline: Vec<(DateTime<Utc>, usize)> = metrics
+        .iter()
+        .enumerate()
+        .flat_map(|(index, metric)| {
+            DueTimes::new(metric, settings, from, until).map(move |at| (at, index))
+        })
+        .collect();
+    timeline.sort();
+    timeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::options;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, min: u32) -> DateTime<Utc> {
+        // A Monday
+        Utc.with_ymd_and_hms(2025, 3, 3, hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_timeline() {
+        let every_20m = Metric {
+            n: 1200,
+            ..Default::default()
+        };
+        let (n, cron) = options::parse_schedule("0 * * * *").unwrap();
+        let hourly = Metric {
+            n,
+            cron,
+            ..Default::default()
+        };
+        let office = Metric {
+            n: 1800,
+            active: options::parse_active("Mon-Fri 09:00-10:00").unwrap(),
+            ..Default::default()
+        };
+        let metrics = vec![every_20m, hourly, office];
+
+        let timeline = timeline(&metrics, &Settings::default(), at(8, 30), at(10, 30));
+        assert_eq!(
+            timeline,
+            vec![
+                (at(8, 30), 0),
+                (at(8, 50), 0),
+                (at(9, 0), 1),
+                (at(9, 0), 2),
+                (at(9, 10), 0),
+                (at(9, 30), 0),
+                (at(9, 30), 2),
+                (at(9, 50), 0),
+                (at(10, 0), 1),
+                (at(10, 10), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_due_times_inactive_every() {
+        let metric = Metric {
+            n: 600,
+            active: options::parse_active("Mon-Fri 09:00-10:00").unwrap(),
+            inactive_every: Some(3600),
+            ..Default::default()
+        };
+        let due: Vec<_> =
+            DueTimes::new(&metric, &Settings::default(), at(7, 0), at(9, 15)).collect();
+        assert_eq!(due, vec![at(7, 0), at(8, 0), at(9, 0), at(9, 10)]);
+    }
+}
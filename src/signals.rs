@@ -0,0 +1,26 @@
+// Unix signals jr reacts to.
+use std::io;
+use std::thread;
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+/// Calls `stop` from a background thread each time jr gets SIGTERM or SIGINT.
+pub fn on_stop(stop: impl Fn() + Send + 'static) -> io::Result<()> {
+    listen(&[SIGTERM, SIGINT], stop)
+}
+
+/// Calls `reload` from a background thread each time jr gets SIGHUP.
+pub fn on_reload(reload: impl Fn() + Send + 'static) -> io::Result<()> {
+    listen(&[SIGHUP], reload)
+}
+
+fn listen(signals: &[libc::c_int], handler: impl Fn() + Send + 'static) -> io::Result<()> {
+    let mut signals = Signals::new(signals)?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            handler();
+        }
+    });
+    Ok(())
+}
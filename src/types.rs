@@ -1,5 +1,15 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::ffi::OsString;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono_tz::Tz;
+
+use crate::config::options;
+use crate::output::{DEFAULT_OUTPUTS, DEFAULT_REPORTER};
+use crate::scheduler::calendar::{Cron, Window};
 
 #[derive(Parser)]
 pub struct Args {
@@ -9,6 +19,10 @@ pub struct Args {
     #[arg(long)]
     pub once: bool,
 
+    /// With --once, print a single line of Nagios plugin output, with perfdata
+    #[arg(long, requires = "once")]
+    pub nagios: bool,
+
     #[arg(short, long)]
     pub name: Option<String>,
 
@@ -21,14 +35,84 @@ pub struct Args {
     #[arg(long)]
     pub max_value: Option<f64>,
 
+    #[arg(long)]
+    pub warn_min_value: Option<f64>,
+
+    #[arg(long)]
+    pub warn_max_value: Option<f64>,
+
+    #[arg(long)]
+    pub max_concurrency: Option<usize>,
+
+    /// Seconds a run may take before it's killed
+    #[arg(long)]
+    pub timeout: Option<f64>,
+
+    /// The configuration file, instead of the first of ./jr.conf,
+    /// $XDG_CONFIG_HOME/jr/jr.conf and /etc/jr/jr.conf
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Don't read any configuration file, only the checks given here
+    #[arg(long, conflicts_with = "config")]
+    pub no_config: bool,
+
+    /// A check in the jr.conf syntax, name::seconds::worker::args. Can be repeated
+    #[arg(long = "check", value_name = "CHECK")]
+    pub checks: Vec<String>,
+
     #[arg(last = true)]
     pub remaining_args: Vec<OsString>,
 
     #[arg(short, long)]
     pub version: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Check the configuration and report every error in it
+    CheckConfig,
+
+    /// List the configured checks, with when they're next due
+    List,
+
+    /// Run a check right away, and show its result and what each output would send
+    Run {
+        /// The name of the check
+        name: String,
+    },
+
+    /// Show when each check would run, without running anything
+    Schedule {
+        /// How far ahead to look, like 90s, 30m, 1h or 2d
+        #[arg(long = "for", value_name = "DURATION", default_value = "1h", value_parser = options::parse_duration)]
+        span: Duration,
+    },
+
+    /// Work with configuration files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Convert a configuration to the TOML format
+    Convert {
+        /// The file to convert, instead of the one jr would read
+        input: Option<PathBuf>,
+
+        /// Where to write the TOML, instead of the standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Metric {
     // From Config
     pub n: u64,
@@ -37,8 +121,37 @@ pub struct Metric {
     pub group: String,
     pub args: String,
     pub short_name: String,
+    /// The range of the values: the check is critical outside of it.
     pub min_value: Option<f64>,
     pub max_value: Option<f64>,
+    /// The check is a warning outside of this range.
+    pub warn_min_value: Option<f64>,
+    pub warn_max_value: Option<f64>,
+    pub outputs: Vec<String>,
+    /// Labels sent along with the results, as `name` or `name=value`.
+    pub tags: Vec<String>,
+    /// Who the results are reported as.
+    pub reporter: String,
+    pub catch_up: CatchUp,
+    /// Each run starts up to this long after its due time, at random.
+    pub jitter: Duration,
+    /// Run on a cron schedule instead of every `n` seconds.
+    pub cron: Option<Cron>,
+    pub timezone: Tz,
+    /// Outside this window the check runs every `inactive_every` seconds, or not at all.
+    pub active: Option<Window>,
+    pub inactive_every: Option<u64>,
+    /// How long a run may take. Defaults to the interval of the check.
+    pub timeout: Option<Duration>,
+    /// How many failed attempts in a row are retried before a failure is reported.
+    pub retries: u32,
+    /// When to retry a failed attempt. Defaults to the interval of the check.
+    pub retry_interval: Option<Duration>,
+    /// Where the check is defined, as `file:line`. Empty for the command line.
+    pub source: String,
+    /// Values expanded from the environment or from files into the configuration
+    /// of the check, see `redact`.
+    pub secrets: Vec<String>,
 
     // From WorkerResult
     pub value: Option<f64>,
@@ -47,7 +160,12 @@ pub struct Metric {
     pub graph_value: Option<i64>,
     pub graph_type: Option<String>,
     pub graph_short_name: Option<String>,
-    pub status: String,
+    pub status: Status,
+    /// How many attempts in a row failed, 0 when the check is ok.
+    pub attempt: u32,
+    /// Whether this is a failed attempt still to be retried. Its status is then
+    /// the one of the last reported (hard) result.
+    pub soft: bool,
 }
 
 impl Default for Metric {
@@ -61,13 +179,357 @@ impl Default for Metric {
             short_name: String::new(),
             min_value: None,
             max_value: None,
+            warn_min_value: None,
+            warn_max_value: None,
+            outputs: DEFAULT_OUTPUTS.iter().map(|o| o.to_string()).collect(),
+            tags: Vec::new(),
+            reporter: DEFAULT_REPORTER.to_string(),
+            catch_up: CatchUp::default(),
+            jitter: Duration::ZERO,
+            cron: None,
+            timezone: Tz::UTC,
+            active: None,
+            inactive_every: None,
+            timeout: None,
+            retries: 0,
+            retry_interval: None,
+            source: String::new(),
+            secrets: Vec::new(),
             value: None,
             units: None,
             message: None,
             graph_value: None,
             graph_type: None,
             graph_short_name: None,
-            status: "ok".to_string(),
+            status: Status::Ok,
+            attempt: 0,
+            soft: false,
         }
     }
 }
+
+impl Metric {
+    /// Whether both checks have the same configuration, whatever their last results
+    /// and wherever they're defined.
+    pub fn same_config(&self, other: &Metric) -> bool {
+        let unplaced = |metric: &Metric| Metric {
+            source: String::new(),
+            ..metric.without_result()
+        };
+        unplaced(self) == unplaced(other)
+    }
+
+    /// `text` with the secrets of the check masked, for printing.
+    pub fn redact(&self, text: &str) -> String {
+        let mut secrets: Vec<&String> = self.secrets.iter().collect();
+        // Longest first, in case a secret contains another one
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        secrets.into_iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), "***")
+        })
+    }
+
+    /// Sets the status from the value, when the worker found nothing wrong: it's
+    /// critical outside of `min_value`..`max_value`, and a warning outside of
+    /// `warn_min_value`..`warn_max_value`.
+    pub fn apply_thresholds(&mut self) {
+        let Some(value) = self.value else {
+            return;
+        };
+        if self.status != Status::Ok {
+            return;
+        }
+        let outside = |min: Option<f64>, max: Option<f64>| {
+            min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max)
+        };
+        if outside(self.min_value, self.max_value) {
+            self.status = Status::Critical;
+        } else if outside(self.warn_min_value, self.warn_max_value) {
+            self.status = Status::Warning;
+        }
+    }
+
+    /// A copy of the configuration of this check, without the result of the last run.
+    pub fn without_result(&self) -> Metric {
+        Metric {
+            value: None,
+            units: None,
+            message: None,
+            graph_value: None,
+            graph_type: None,
+            graph_short_name: None,
+            status: Status::Ok,
+            attempt: 0,
+            soft: false,
+            ..self.clone()
+        }
+    }
+}
+
+/// The result of a check.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Status {
+    #[default]
+    Ok,
+    /// The value is outside of the warning thresholds.
+    Warning,
+    /// The check failed, or its value is outside of its range.
+    Critical,
+    /// The check couldn't tell, e.g. what it looks at doesn't exist.
+    Unknown,
+    /// The run was killed after its timeout.
+    Timeout,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Critical => "critical",
+            Status::Unknown => "unknown",
+            Status::Timeout => "timeout",
+        }
+    }
+
+    /// The status as the angelweb and graphite outputs have always sent it, with a
+    /// failed check as `error`, for the dashboards built on it.
+    pub fn legacy_str(&self) -> &'static str {
+        match self {
+            Status::Critical => "error",
+            status => status.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// What the scheduler does with the runs of a check it missed, because the check
+/// was still running or jr fell behind.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CatchUp {
+    /// Forget about them and wait for the next due time.
+    Skip,
+    /// Run the check once, as soon as possible.
+    #[default]
+    Once,
+    /// Run the check once for every missed run.
+    All,
+}
+
+impl FromStr for CatchUp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(CatchUp::Skip),
+            "once" => Ok(CatchUp::Once),
+            "all" => Ok(CatchUp::All),
+            _ => Err(format!(
+                "Invalid catch-up policy '{}', expected skip, once or all",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatchUp::Skip => write!(f, "skip"),
+            CatchUp::Once => write!(f, "once"),
+            CatchUp::All => write!(f, "all"),
+        }
+    }
+}
+
+/// How the first run of each check is spread out after jr starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Splay {
+    /// Every check runs right away.
+    #[default]
+    None,
+    /// Each check waits a random offset.
+    Random,
+    /// Each check waits an offset derived from its name, the same on every start.
+    Hash,
+}
+
+impl FromStr for Splay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Splay::None),
+            "random" => Ok(Splay::Random),
+            "hash" => Ok(Splay::Hash),
+            _ => Err(format!(
+                "Invalid splay '{}', expected none, random or hash",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Splay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Splay::None => write!(f, "none"),
+            Splay::Random => write!(f, "random"),
+            Splay::Hash => write!(f, "hash"),
+        }
+    }
+}
+
+/// Settings that apply to jr as a whole rather than to a single check.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub max_concurrency: Option<usize>,
+    /// The outputs of the checks that don't belong to any group, used for jr's own metrics.
+    pub outputs: Option<Vec<String>>,
+    pub splay: Option<Splay>,
+    /// The largest startup offset. Offsets are also kept below each check's interval.
+    pub splay_max: Option<Duration>,
+    /// How long running checks are waited for when jr is stopped.
+    pub shutdown_timeout: Option<Duration>,
+    /// How often jr.conf is looked at for changes, to reload it.
+    pub watch_config: Option<Duration>,
+    /// The configuration file given on the command line.
+    pub config_file: Option<PathBuf>,
+    /// Don't read any configuration file.
+    pub no_config: bool,
+    /// Print the results of `--once` as a Nagios plugin does.
+    pub nagios: bool,
+}
+
+impl Settings {
+    /// Overrides our settings with the ones set in `other`.
+    pub fn merge(&mut self, other: Settings) {
+        if other.max_concurrency.is_some() {
+            self.max_concurrency = other.max_concurrency;
+        }
+        if other.outputs.is_some() {
+            self.outputs = other.outputs;
+        }
+        if other.splay.is_some() {
+            self.splay = other.splay;
+        }
+        if other.splay_max.is_some() {
+            self.splay_max = other.splay_max;
+        }
+        if other.shutdown_timeout.is_some() {
+            self.shutdown_timeout = other.shutdown_timeout;
+        }
+        if other.watch_config.is_some() {
+            self.watch_config = other.watch_config;
+        }
+        if other.config_file.is_some() {
+            self.config_file = other.config_file;
+        }
+        self.no_config |= other.no_config;
+        self.nagios |= other.nagios;
+    }
+
+    /// The directives that differ in `new` and only take effect on a restart, as a
+    /// reload keeps the running pool, watcher and schedule, and the outputs of jr's
+    /// own metrics. The outputs of the checks are reloaded with them.
+    pub fn needing_restart(&self, new: &Settings) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.max_concurrency != new.max_concurrency {
+            changed.push("Max-concurrency");
+        }
+        if self.splay != new.splay || self.splay_max != new.splay_max {
+            changed.push("Splay");
+        }
+        if self.shutdown_timeout != new.shutdown_timeout {
+            changed.push("Shutdown-timeout");
+        }
+        if self.watch_config != new.watch_config {
+            changed.push("Watch-config");
+        }
+        if self.outputs != new.outputs {
+            changed.push("Outputs");
+        }
+        changed
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub checks: Vec<Metric>,
+    pub settings: Settings,
+    /// What was wrong with the configuration. The lines in error are left out.
+    pub errors: Vec<String>,
+    /// The files and directories the configuration was read from.
+    pub files: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Adds the checks of `other`; its settings win over ours.
+    pub fn extend(&mut self, other: Config) {
+        self.checks.extend(other.checks);
+        self.settings.merge(other.settings);
+        self.errors.extend(other.errors);
+        self.files.extend(other.files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_thresholds() {
+        let disk = |value: f64| Metric {
+            value: Some(value),
+            max_value: Some(95.0),
+            warn_max_value: Some(80.0),
+            warn_min_value: Some(1.0),
+            ..Default::default()
+        };
+        let status = |mut metric: Metric| {
+            metric.apply_thresholds();
+            metric.status
+        };
+
+        assert_eq!(status(disk(50.0)), Status::Ok);
+        assert_eq!(status(disk(85.0)), Status::Warning);
+        assert_eq!(status(disk(0.5)), Status::Warning);
+        assert_eq!(status(disk(96.0)), Status::Critical);
+        // What the worker found stays
+        let failed = Metric {
+            status: Status::Unknown,
+            ..disk(96.0)
+        };
+        assert_eq!(status(failed), Status::Unknown);
+        let no_value = Metric {
+            value: None,
+            ..disk(96.0)
+        };
+        assert_eq!(status(no_value), Status::Ok);
+    }
+
+    #[test]
+    fn test_settings_needing_restart() {
+        let settings = Settings {
+            max_concurrency: Some(4),
+            splay: Some(Splay::Hash),
+            ..Default::default()
+        };
+        let new = Settings {
+            splay_max: Some(Duration::from_secs(30)),
+            watch_config: Some(Duration::from_secs(5)),
+            outputs: Some(vec!["stdout".to_string()]),
+            ..settings.clone()
+        };
+        assert!(settings.needing_restart(&settings).is_empty());
+        assert_eq!(
+            settings.needing_restart(&new),
+            vec!["Splay", "Watch-config", "Outputs"]
+        );
+    }
+}
@@ -1,20 +1,44 @@
 use std::time::Instant;
 
-use crate::types::Metric;
+use reqwest::blocking::Client;
 
-pub fn run(mut metric: Metric) -> Metric {
+use crate::types::{Metric, Status};
+use crate::worker::Worker;
+
+/// Times an HTTP GET. The client is kept between runs so connections are reused.
+#[derive(Default)]
+pub struct CheckUrl {
+    client: Option<Client>,
+}
+
+impl Worker for CheckUrl {
+    fn name(&self) -> &'static str {
+        "check_url"
+    }
+
+    fn run(&mut self, metric: Metric) -> Metric {
+        let client = self.client.get_or_insert_with(Client::new);
+        check(client, metric)
+    }
+}
+
+fn check(client: &Client, mut metric: Metric) -> Metric {
     let url = match metric.args.trim().is_empty() {
         true => "https://lwn.net",
         false => metric.args.trim(),
     };
-    println!("Checking {}", url);
+    println!("Checking {}", metric.redact(url));
 
     // Start the timer
     let start_time = Instant::now();
     metric.graph_short_name = Some(metric.short_name.clone());
 
     // Make the HTTP request
-    match reqwest::blocking::get(url) {
+    let mut request = client.get(url);
+    if let Some(timeout) = metric.timeout {
+        request = request.timeout(timeout);
+    }
+    match request.send() {
         Ok(response) => {
             // Calculate the time taken
             let duration = start_time.elapsed().as_millis() as f64;
@@ -31,14 +55,14 @@ pub fn run(mut metric: Metric) -> Metric {
                 metric.units = Some("ms".to_string());
                 metric.message = Some(format!("HTTP error: {}", response.status()));
                 metric.graph_value = Some(duration as i64);
-                metric.status = "error".to_string();
+                metric.status = Status::Critical;
             }
         }
         Err(_e) => {
             metric.value = Some(0.0);
             metric.units = Some("ms".to_string());
             metric.message = Some("ERROR".to_string());
-            metric.status = "error".to_string();
+            metric.status = Status::Critical;
         }
     }
     metric
@@ -55,7 +79,7 @@ mod tests {
             args: "https://www.google.com".to_string(),
             ..Default::default()
         };
-        let result = run(metric);
+        let result = CheckUrl::default().run(metric);
         assert_eq!(result.message, Some("Success".to_string()));
         assert!(result.value.unwrap() > 0.0);
     }
@@ -66,7 +90,7 @@ mod tests {
             args: "https://nonexistent.url.fail".to_string(),
             ..Default::default()
         };
-        let result = run(metric);
+        let result = CheckUrl::default().run(metric);
         assert_eq!(result.message, Some("ERROR".to_string()));
         assert_eq!(result.value, Some(0.0));
     }
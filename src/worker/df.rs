@@ -1,6 +1,26 @@
-use crate::types::Metric;
+use crate::types::{Metric, Status};
+use crate::worker::Worker;
 use sysinfo::Disks;
 
+pub struct Df;
+
+impl Worker for Df {
+    fn name(&self) -> &'static str {
+        "df"
+    }
+
+    fn validate_args(&self, args: &str) -> Result<(), String> {
+        if args.trim().is_empty() {
+            return Err("df needs a mount point".to_string());
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, metric: Metric) -> Metric {
+        run(metric)
+    }
+}
+
 pub fn run(mut metric: Metric) -> Metric {
     let disks = Disks::new_with_refreshed_list();
     let path = metric.args.clone();
@@ -21,8 +41,7 @@ pub fn run(mut metric: Metric) -> Metric {
         }
     }
 
-    metric.value = Some(0.0);
-    metric.graph_value = Some(0);
+    metric.status = Status::Unknown;
     metric.message = Some(format!("Filesystem '{}' not found", path));
     metric
 }
@@ -1,6 +1,19 @@
 use crate::types::Metric;
+use crate::worker::Worker;
 use sysinfo::System;
 
+pub struct LoadAvg;
+
+impl Worker for LoadAvg {
+    fn name(&self) -> &'static str {
+        "load_avg"
+    }
+
+    fn run(&mut self, metric: Metric) -> Metric {
+        run(metric)
+    }
+}
+
 pub fn run(mut metric: Metric) -> Metric {
     // Initialize the system info
     let mut system = System::new_all();
@@ -1,6 +1,120 @@
+use std::collections::BTreeMap;
+
+use crate::types::Metric;
+
 pub mod check_url;
 pub mod df;
 pub mod load_avg;
+pub mod process;
 pub mod query_api;
 pub mod runthis;
 pub mod timethis;
+
+/// A worker plugin. One instance is created per check, so a worker can keep
+/// state (connections, previous counters...) between runs of that check.
+pub trait Worker: Send {
+    /// The name used in the `test` field of `jr.conf`.
+    fn name(&self) -> &'static str;
+
+    /// Checks the arguments of a check before it is scheduled.
+    fn validate_args(&self, _args: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once, before the first run of the check.
+    fn setup(&mut self, _metric: &Metric) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs the check and returns the metric updated with the result.
+    fn run(&mut self, metric: Metric) -> Metric;
+
+    /// Called once, when the check is removed or jr exits.
+    fn teardown(&mut self) {}
+}
+
+pub type WorkerFactory = fn() -> Box<dyn Worker>;
+
+/// Maps worker names to factories. Each check gets its own worker instance.
+#[derive(Default)]
+pub struct Registry {
+    factories: BTreeMap<&'static str, WorkerFactory>,
+}
+
+impl Registry {
+    /// A registry with all the workers shipped with jr.
+    pub fn with_builtins() -> Self {
+        let mut registry = Registry::default();
+        registry.register(|| Box::new(check_url::CheckUrl::default()));
+        registry.register(|| Box::new(df::Df));
+        registry.register(|| Box::new(load_avg::LoadAvg));
+        registry.register(|| Box::new(query_api::QueryApi::default()));
+        registry.register(|| Box::new(runthis::RunThis));
+        registry.register(|| Box::new(timethis::TimeThis));
+        registry
+    }
+
+    /// Registers a worker under the name it reports.
+    pub fn register(&mut self, factory: WorkerFactory) {
+        let name = factory().name();
+        self.factories.insert(name, factory);
+    }
+
+    /// Creates a new instance of the worker called `name`.
+    pub fn create(&self, name: &str) -> Option<Box<dyn Worker>> {
+        self.factories.get(name).map(|factory| factory())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter {
+        runs: u32,
+    }
+
+    impl Worker for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn run(&mut self, mut metric: Metric) -> Metric {
+            self.runs += 1;
+            metric.value = Some(self.runs as f64);
+            metric
+        }
+    }
+
+    #[test]
+    fn test_registry_builtins() {
+        let registry = Registry::with_builtins();
+        for name in [
+            "check_url",
+            "df",
+            "load_avg",
+            "query_api",
+            "runthis",
+            "timethis",
+        ] {
+            let worker = registry.create(name).unwrap();
+            assert_eq!(worker.name(), name);
+        }
+        assert!(registry.create("check_ulr").is_none());
+    }
+
+    #[test]
+    fn test_worker_keeps_state_between_runs() {
+        let mut registry = Registry::default();
+        registry.register(|| Box::new(Counter { runs: 0 }));
+
+        let mut worker = registry.create("counter").unwrap();
+        worker.run(Metric::default());
+        let result = worker.run(Metric::default());
+        assert_eq!(result.value, Some(2.0));
+
+        // A new instance starts from scratch
+        let mut other = registry.create("counter").unwrap();
+        assert_eq!(other.run(Metric::default()).value, Some(1.0));
+    }
+}
@@ -0,0 +1,132 @@
+// Child processes of command workers. Each child runs in its own process group,
+// registered on the run it belongs to, so the scheduler can kill the whole group
+// (the command and anything it started) when the run times out.
+use std::cell::RefCell;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Shared between the scheduler and the thread running a check.
+#[derive(Clone, Default)]
+pub struct RunHandle(Arc<Mutex<RunState>>);
+
+#[derive(Default)]
+struct RunState {
+    started: Option<Instant>,
+    process_group: Option<u32>,
+    killed: bool,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<RunHandle>> = const { RefCell::new(None) };
+}
+
+impl RunHandle {
+    /// Marks the run as started and makes it the current run of this thread.
+    pub fn start(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            state.started = Some(Instant::now());
+        }
+        CURRENT.with(|current| *current.borrow_mut() = Some(self.clone()));
+    }
+
+    /// When the run started, if it did.
+    pub fn started(&self) -> Option<Instant> {
+        self.0.lock().ok().and_then(|state| state.started)
+    }
+
+    /// Kills the process group of the run, and any group it starts from now on.
+    pub fn kill(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            state.killed = true;
+            if let Some(process_group) = state.process_group.take() {
+                kill_group(process_group);
+            }
+        }
+    }
+
+    pub fn killed(&self) -> bool {
+        self.0.lock().map(|state| state.killed).unwrap_or(false)
+    }
+}
+
+/// Ends the current run of this thread.
+pub fn finish() {
+    CURRENT.with(|current| current.borrow_mut().take());
+}
+
+/// Spawns `command` in a new process group, registered on the current run.
+pub fn spawn(command: &mut Command) -> io::Result<Child> {
+    let child = command.process_group(0).spawn()?;
+    CURRENT.with(|current| {
+        if let Some(RunHandle(state)) = &*current.borrow() {
+            if let Ok(mut state) = state.lock() {
+                if state.killed {
+                    kill_group(child.id());
+                } else {
+                    state.process_group = Some(child.id());
+                }
+            }
+        }
+    });
+    Ok(child)
+}
+
+/// Unregisters the process group of `child` once it has been waited for, so its
+/// id can't be killed after being reused.
+pub fn reaped(child: &Child) {
+    CURRENT.with(|current| {
+        if let Some(RunHandle(state)) = &*current.borrow() {
+            if let Ok(mut state) = state.lock() {
+                if state.process_group == Some(child.id()) {
+                    state.process_group = None;
+                }
+            }
+        }
+    });
+}
+
+/// Whether the current run of this thread was killed.
+pub fn killed() -> bool {
+    CURRENT.with(|current| current.borrow().as_ref().is_some_and(RunHandle::killed))
+}
+
+fn kill_group(process_group: u32) {
+    // SAFETY: kill() has no memory safety requirements. A negative pid targets the group.
+    unsafe {
+        libc::kill(-(process_group as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_kill_process_group() {
+        let handle = RunHandle::default();
+        let run = handle.clone();
+        let worker = thread::spawn(move || {
+            run.start();
+            // The shell starts a child of its own, which must be killed too
+            let mut child = spawn(Command::new("sh").args(["-c", "sleep 30; true"])).unwrap();
+            let status = child.wait().unwrap();
+            reaped(&child);
+            let killed = killed();
+            finish();
+            (status, killed)
+        });
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(handle.started().is_some());
+        handle.kill();
+
+        let (status, killed) = worker.join().unwrap();
+        assert!(!status.success());
+        assert!(killed);
+    }
+}
@@ -4,7 +4,8 @@ use std::env;
 use reqwest::blocking::Client;
 use serde_json::Value;
 
-use crate::types::Metric;
+use crate::types::{Metric, Status};
+use crate::worker::Worker;
 
 fn parse_args(args: &str) -> HashMap<String, String> {
     let mut map = HashMap::new();
@@ -16,12 +17,49 @@ fn parse_args(args: &str) -> HashMap<String, String> {
     map
 }
 
+/// The `header=Name:value` arguments, which can be repeated.
+fn parse_headers(args: &str) -> Vec<(&str, &str)> {
+    args.split_whitespace()
+        .filter_map(|part| part.strip_prefix("header="))
+        .filter_map(|header| header.split_once(':'))
+        .collect()
+}
+
 /// A worker plugin that queries an API and extracts a numeric value from its JSON response
 /// using a jq-like path traversal.
 ///
 /// Example configuration line in `jr.conf`:
 /// `dolarapi_blue_venta::300::query_api::url=https://dolarapi.com/v1/dolares/blue jq=.venta`
-pub fn run(mut metric: Metric) -> Metric {
+///
+/// Headers are given with `header=Name:value`, and bearer tokens with `bearer=token`.
+#[derive(Default)]
+pub struct QueryApi {
+    client: Option<Client>,
+}
+
+impl Worker for QueryApi {
+    fn name(&self) -> &'static str {
+        "query_api"
+    }
+
+    fn validate_args(&self, args: &str) -> Result<(), String> {
+        let args = parse_args(args);
+        if !args.contains_key("url") {
+            return Err("url is a mandatory argument".to_string());
+        }
+        if !args.contains_key("jq") {
+            return Err("jq path is mandatory for this worker".to_string());
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, metric: Metric) -> Metric {
+        let client = self.client.get_or_insert_with(Client::new);
+        query(client, metric)
+    }
+}
+
+fn query(client: &Client, mut metric: Metric) -> Metric {
     let args = parse_args(&metric.args);
 
     let debug_enabled = env::var("DEBUG").is_ok_and(|v| v == "1");
@@ -29,7 +67,7 @@ pub fn run(mut metric: Metric) -> Metric {
     let url = match args.get("url") {
         Some(url) => url,
         None => {
-            metric.status = "error".to_string();
+            metric.status = Status::Critical;
             metric.message = Some("url is a mandatory argument".to_string());
             metric.value = Some(-1.0);
             return metric;
@@ -44,21 +82,30 @@ pub fn run(mut metric: Metric) -> Metric {
 
     metric.graph_short_name = Some(metric.short_name.clone());
 
-    let client = Client::new();
-    let request_builder = match method.as_str() {
+    let mut request_builder = match method.as_str() {
         "GET" => client.get(url),
         "POST" => client.post(url),
         "PUT" => client.put(url),
         "DELETE" => client.delete(url),
         "PATCH" => client.patch(url),
         _ => {
-            metric.status = "error".to_string();
+            metric.status = Status::Critical;
             metric.message = Some(format!("Unsupported HTTP method: {}", method));
             metric.value = Some(-1.0);
             return metric;
         }
     };
 
+    for (name, value) in parse_headers(&metric.args) {
+        request_builder = request_builder.header(name, value);
+    }
+    if let Some(token) = args.get("bearer") {
+        request_builder = request_builder.bearer_auth(token);
+    }
+    if let Some(timeout) = metric.timeout {
+        request_builder = request_builder.timeout(timeout);
+    }
+
     match request_builder.send() {
         Ok(response) => {
             if response.status().is_success() {
@@ -77,7 +124,7 @@ pub fn run(mut metric: Metric) -> Metric {
                                 if let Some(next_value) = current_value.get(key) {
                                     current_value = next_value;
                                 } else {
-                                    metric.status = "error".to_string();
+                                    metric.status = Status::Critical;
                                     metric.message =
                                         Some(format!("jq path error: key '{}' not found", key));
                                     metric.value = Some(-1.0);
@@ -87,12 +134,12 @@ pub fn run(mut metric: Metric) -> Metric {
                             if let Some(v) = current_value.as_f64() {
                                 metric.value = Some(v);
                                 metric.graph_value = Some(v as i64); // Set graph_value here
-                                metric.status = "ok".to_string();
+                                metric.status = Status::Ok;
                                 if debug_enabled {
                                     println!("DEBUG: JQ traversal output (numeric): {}", v);
                                 }
                             } else {
-                                metric.status = "error".to_string();
+                                metric.status = Status::Critical;
                                 metric.message = Some(format!(
                                     "jq path error: value at '{}' is not a number",
                                     path
@@ -106,27 +153,27 @@ pub fn run(mut metric: Metric) -> Metric {
                                 }
                             }
                         } else {
-                            metric.status = "error".to_string();
+                            metric.status = Status::Critical;
                             metric.message =
                                 Some("jq path is mandatory for this worker".to_string());
                             metric.value = Some(-1.0);
                         }
                     }
                     Err(e) => {
-                        metric.status = "error".to_string();
+                        metric.status = Status::Critical;
                         metric.message = Some(format!("JSON parsing error: {}", e));
                         metric.value = Some(-1.0);
                     }
                 }
             } else {
-                metric.status = "error".to_string();
+                metric.status = Status::Critical;
                 metric.message = Some(format!("HTTP error: {}", response.status()));
                 metric.value = Some(-1.0);
             }
         }
         Err(e) => {
-            metric.status = "error".to_string();
-            metric.message = Some(format!("Request error: {}", e));
+            metric.status = Status::Critical;
+            metric.message = Some(metric.redact(&format!("Request error: {}", e)));
             metric.value = Some(-1.0);
         }
     }
@@ -137,7 +184,7 @@ pub fn run(mut metric: Metric) -> Metric {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::Metric;
+    use crate::types::{Metric, Status};
     use httptest::{matchers::*, responders::*, Expectation, Server};
 
     #[test]
@@ -146,8 +193,8 @@ mod tests {
             args: "method=GET".to_string(),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "error");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Critical);
         assert_eq!(
             result.message,
             Some("url is a mandatory argument".to_string())
@@ -167,8 +214,8 @@ mod tests {
             args: format!("url={}", server.url("/")),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "error");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Critical);
         assert_eq!(
             result.message,
             Some("jq path is mandatory for this worker".to_string())
@@ -188,8 +235,8 @@ mod tests {
             args: format!("url={} jq=.data.value", server.url("/")),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "ok");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Ok);
         assert_eq!(result.value, Some(42.0));
         assert_eq!(result.graph_value, Some(42)); // New assertion
     }
@@ -206,8 +253,8 @@ mod tests {
             args: format!("url={} jq=.data.nonexistent", server.url("/")),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "error");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Critical);
         assert_eq!(
             result.message,
             Some("jq path error: key 'nonexistent' not found".to_string())
@@ -228,8 +275,8 @@ mod tests {
             args: format!("url={} jq=.data.value", server.url("/")),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "error");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Critical);
         assert_eq!(
             result.message,
             Some("jq path error: value at '.data.value' is not a number".to_string())
@@ -248,8 +295,8 @@ mod tests {
             args: format!("url={} jq=.status", server.url("/")),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "error");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Critical);
         assert_eq!(
             result.message,
             Some("HTTP error: 404 Not Found".to_string())
@@ -263,9 +310,44 @@ mod tests {
             args: "url=http://localhost:12345 jq=.status".to_string(),
             ..Default::default()
         };
-        let result = run(metric);
-        assert_eq!(result.status, "error");
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Critical);
         assert!(result.message.unwrap().contains("Request error"));
         assert_eq!(result.value, Some(-1.0));
     }
+
+    #[test]
+    fn test_query_api_headers() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", "/"),
+                request::headers(contains(("authorization", "Bearer s3cr3t"))),
+                request::headers(contains(("x-team", "ops"))),
+            ])
+            .respond_with(json_encoded(serde_json::json!({"value": 1}))),
+        );
+
+        let metric = Metric {
+            args: format!(
+                "url={} jq=.value bearer=s3cr3t header=X-Team:ops",
+                server.url("/")
+            ),
+            ..Default::default()
+        };
+        let result = QueryApi::default().run(metric);
+        assert_eq!(result.status, Status::Ok);
+    }
+
+    #[test]
+    fn test_query_api_redacts_secrets() {
+        let metric = Metric {
+            args: "url=http://localhost:12345/?token=s3cr3t jq=.status".to_string(),
+            secrets: vec!["s3cr3t".to_string()],
+            ..Default::default()
+        };
+        let result = QueryApi::default().run(metric);
+        let message = result.message.unwrap();
+        assert!(!message.contains("s3cr3t"), "{}", message);
+    }
 }
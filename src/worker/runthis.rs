@@ -1,8 +1,28 @@
-use crate::types::Metric;
+use crate::types::{Metric, Status};
+use crate::worker::{process, Worker};
 use std::io::{self, Read};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
 
+pub struct RunThis;
+
+impl Worker for RunThis {
+    fn name(&self) -> &'static str {
+        "runthis"
+    }
+
+    fn validate_args(&self, args: &str) -> Result<(), String> {
+        if args.trim().is_empty() {
+            return Err("runthis needs a command to run".to_string());
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, metric: Metric) -> Metric {
+        run(metric)
+    }
+}
+
 pub fn run(mut metric: Metric) -> Metric {
     let command = metric.args.as_str();
 
@@ -28,7 +48,7 @@ pub fn run(mut metric: Metric) -> Metric {
             metric.message = Some("Failed to execute command".to_string());
             metric.graph_value = Some(-1);
             metric.graph_short_name = Some(metric.short_name.clone());
-            metric.status = "error".to_string();
+            metric.status = Status::Critical;
         }
     }
     metric
@@ -47,11 +67,12 @@ fn run_command(command: &str) -> io::Result<String> {
     let args = &args[1..];
 
     // Spawn the command with standard input set to ignore and capture output and error
-    let mut child = Command::new(cmd)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
+    let mut child = process::spawn(
+        Command::new(cmd)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
 
     // Read stdout and stderr into buffers
     let mut stdout = String::new();
@@ -66,16 +87,19 @@ fn run_command(command: &str) -> io::Result<String> {
     }
 
     // Wait for the command to finish and get the exit status
-    let status = child.wait()?;
+    let status = child.wait();
+    process::reaped(&child);
+    let status = status?;
+
+    if process::killed() {
+        return Err(io::Error::other("Command killed after timing out"));
+    }
 
     if !status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Command failed with status: {}. Error output: {}",
-                status, stderr
-            ),
-        ));
+        return Err(io::Error::other(format!(
+            "Command failed with status: {}. Error output: {}",
+            status, stderr
+        )));
     }
 
     // Return the captured standard output
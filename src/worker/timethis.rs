@@ -1,8 +1,28 @@
-use crate::types::Metric;
+use crate::types::{Metric, Status};
+use crate::worker::{process, Worker};
 use std::io;
 use std::process::Command;
 use std::time::Instant;
 
+pub struct TimeThis;
+
+impl Worker for TimeThis {
+    fn name(&self) -> &'static str {
+        "timethis"
+    }
+
+    fn validate_args(&self, args: &str) -> Result<(), String> {
+        if args.trim().is_empty() {
+            return Err("timethis needs a command to run".to_string());
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, metric: Metric) -> Metric {
+        run(metric)
+    }
+}
+
 pub fn run(mut metric: Metric) -> Metric {
     let command = metric.args.as_str();
 
@@ -21,12 +41,12 @@ pub fn run(mut metric: Metric) -> Metric {
         }
         Err(e) => {
             eprintln!("Failed to execute command: {}", e);
-            metric.value = Some((start.elapsed().as_millis() as f64) * -1.0);
+            metric.value = Some(-(start.elapsed().as_millis() as f64));
             metric.units = Some("ms".to_string());
             metric.message = Some("Failed to execute command".to_string());
             metric.graph_value = Some(-(start.elapsed().as_millis() as i64));
             metric.graph_short_name = Some(metric.short_name.clone());
-            metric.status = "error".to_string();
+            metric.status = Status::Critical;
         }
     }
     metric
@@ -45,16 +65,22 @@ fn run_command(command: &str) -> io::Result<()> {
     let args = &args[1..];
 
     // Spawn the command with standard input, output, and error set to ignore
-    let mut child = Command::new(cmd).args(args).spawn()?;
+    let mut child = process::spawn(Command::new(cmd).args(args))?;
 
     // Wait for the command to finish and get the exit status
-    let status = child.wait()?;
+    let status = child.wait();
+    process::reaped(&child);
+    let status = status?;
+
+    if process::killed() {
+        return Err(io::Error::other("Command killed after timing out"));
+    }
 
     if !status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Command failed with status: {}", status),
-        ));
+        return Err(io::Error::other(format!(
+            "Command failed with status: {}",
+            status
+        )));
     }
     Ok(())
 }
@@ -16,6 +16,7 @@ fn test_min_max_value_angelweb_output() {
         .arg("run")
         .arg("--")
         .arg("--once")
+        .arg("--no-config")
         .arg("--name")
         .arg("test_metric")
         .arg("--min-value")
@@ -30,9 +31,10 @@ fn test_min_max_value_angelweb_output() {
         .output()
         .expect("Failed to execute command");
 
-    // Check if the command ran successfully
-    assert!(
-        output.status.success(),
+    // sleep 0.1 takes more than max_value milliseconds: CRITICAL
+    assert_eq!(
+        output.status.code(),
+        Some(2),
         "Command failed with stderr: {}",
         String::from_utf8_lossy(&output.stderr)
     );
@@ -45,6 +47,7 @@ fn test_min_max_value_angelweb_output() {
     assert_eq!(json_payload["min_value"].as_f64(), Some(10.5));
     assert_eq!(json_payload["max_value"].as_f64(), Some(20.5));
     assert_eq!(json_payload["short_name"].as_str(), Some("test_metric"));
+    assert_eq!(json_payload["status"].as_str(), Some("error"));
 
     // The temporary file will be automatically deleted when `temp_file` goes out of scope
 }
@@ -62,6 +65,7 @@ fn test_graph_type_angelweb_output() {
         .arg("run")
         .arg("--")
         .arg("--once")
+        .arg("--no-config")
         .arg("--name")
         .arg("test_graph_type_metric")
         .arg("--worker")
@@ -105,6 +109,7 @@ fn test_status_error_angelweb_output() {
         .arg("run")
         .arg("--")
         .arg("--once")
+        .arg("--no-config")
         .arg("--name")
         .arg("test_status_error")
         .arg("--worker")
@@ -115,8 +120,10 @@ fn test_status_error_angelweb_output() {
         .output()
         .expect("Failed to execute command");
 
-    assert!(
-        output.status.success(),
+    // CRITICAL, as a Nagios plugin
+    assert_eq!(
+        output.status.code(),
+        Some(2),
         "Command failed with stderr: {}",
         String::from_utf8_lossy(&output.stderr)
     );
@@ -145,6 +152,7 @@ fn test_status_ok_angelweb_output() {
         .arg("run")
         .arg("--")
         .arg("--once")
+        .arg("--no-config")
         .arg("--name")
         .arg("test_status_ok")
         .arg("--worker")
@@ -182,6 +190,7 @@ fn test_df_worker() {
         .arg("run")
         .arg("--")
         .arg("--once")
+        .arg("--no-config")
         .arg("--name")
         .arg("test_df")
         .arg("--worker")
@@ -206,3 +215,50 @@ fn test_df_worker() {
     let value = json_payload["graph_value"].as_u64().unwrap();
     assert!(value <= 100);
 }
+
+#[test]
+fn test_nagios_output() {
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--once")
+        .arg("--nagios")
+        .arg("--no-config")
+        .arg("--check")
+        .arg("up::60::timethis::true")
+        .arg("--check")
+        .arg("down::60::runthis::false")
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "Unexpected output: {}", stdout);
+    assert!(lines[0].starts_with("JR CRITICAL - down CRITICAL: "));
+    assert!(lines[0].contains(" | up="));
+}
+
+#[test]
+fn test_timed_out_check_is_retried() {
+    // The retry waits for the killed run to hand its worker back
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--once")
+        .arg("--nagios")
+        .arg("--no-config")
+        .arg("--check")
+        .arg("hung;timeout=1;retries=1::60::runthis::sleep 30")
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(
+        output.status.code(),
+        Some(2),
+        "Command failed with stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("JR CRITICAL - hung: Timed out after 1s"));
+}